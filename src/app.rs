@@ -1,241 +1,308 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{self, Path, PathBuf};
+
+use encoding_rs::Encoding;
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+
+use crate::document::{Document, View};
+use crate::explorer::Explorer;
+use crate::finder::Finder;
+use crate::pane::{Orientation, Pane, PaneLayout};
+use crate::swap;
+use crate::watch::FileWatch;
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum InputMode {
     Normal,
     Search,
     GoToLine,
     ConfirmQuit,
+    ConfirmClose,
+    BufferList,
+    FindFile,
+    Explorer,
+    ExplorerCreate,
+    ExplorerRename,
+    ConfirmDelete,
+    UndoTree,
+    ReopenEncoding,
+    SaveEncoding,
+    SaveAs,
+    ConfirmBinaryEdit,
+    ExternalChange,
+    DiskDiff,
 }
 
-#[derive(Clone)]
-pub struct EditorState {
-    pub content: Vec<String>,
-    pub cursor_row: usize,
-    pub cursor_col: usize,
-}
-
-#[derive(Clone, Copy, PartialEq)]
-pub struct Selection {
-    pub start_row: usize,
-    pub start_col: usize,
-    pub end_row: usize,
-    pub end_col: usize,
+// A one-line message shown in the footer until the next key press.
+pub struct StatusMessage {
+    pub text: String,
+    pub is_error: bool,
 }
 
-
 pub struct App {
     pub exit: bool,
-    pub filename: String,
-    pub content: Vec<String>,
-    pub cursor_row: usize,
-    pub cursor_col: usize,
-    pub modified: bool,
-    pub scroll_offset: usize,
-    pub undo_stack: Vec<EditorState>,
-    pub redo_stack: Vec<EditorState>,
+    // Every open buffer, in the order they were opened. Never empty.
+    pub documents: Vec<Document>,
+    // The document shown in the focused pane.
+    pub active: usize,
+    pub panes: Vec<Pane>,
+    pub layout: PaneLayout,
+    pub focus: usize,
+    // The file tree, once it has been shown.
+    pub explorer: Option<Explorer>,
+    pub show_explorer: bool,
+    // The file finder while it is open.
+    pub finder: Option<Finder>,
+    pub disk_diff: Vec<String>,
+    pub disk_diff_scroll: usize,
+    pub side_margin: usize,
+    // Whether newly opened buffers start with soft wrap on, and follow their
+    // file as it changes on disk.
+    pub soft_wrap: bool,
+    pub watch: bool,
+    pub undo_tree_index: usize,
+    pub buffer_list_index: usize,
     pub input_mode: InputMode,
     pub input_buffer: String,
-    pub search_query: String,
-    pub search_matches: Vec<(usize, usize)>,
-    pub search_index: usize,
-    pub selection: Option<Selection>,
+    // The encoding chosen with "save with encoding" while that save waits
+    // on a prompt, to be used if the prompt ends in a save.
+    pub pending_encoding: Option<(&'static Encoding, bool)>,
     pub clipboard: String,
+    pub status: Option<StatusMessage>,
 }
 
 impl App {
     pub fn new() -> App {
         App {
-        
             exit: false,
-            filename: String::new(),
-            content: Vec::new(),
-            cursor_row: 0,
-            cursor_col: 0,
-            modified: false,
-            scroll_offset: 0,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
+            documents: Vec::new(),
+            active: 0,
+            panes: vec![Pane::new(0, View::default())],
+            layout: PaneLayout::Pane(0),
+            focus: 0,
+            explorer: None,
+            show_explorer: false,
+            finder: None,
+            disk_diff: Vec::new(),
+            disk_diff_scroll: 0,
+            side_margin: 4,
+            soft_wrap: false,
+            watch: false,
+            undo_tree_index: 0,
+            buffer_list_index: 0,
             input_mode: InputMode::Normal,
             input_buffer: String::new(),
-            search_query: String::new(),
-            search_matches: Vec::new(),
-            search_index: 0,
-            selection: None,
+            pending_encoding: None,
             clipboard: String::new(),
+            status: None,
         }
     }
 
-    pub fn save_state(&mut self) {
-        self.undo_stack.push(EditorState {
-            content: self.content.clone(),
-            cursor_row: self.cursor_row,
-            cursor_col: self.cursor_col,    
-        });
+    pub fn doc(&self) -> &Document {
+        &self.documents[self.active]
+    }
 
-        self.redo_stack.clear();
+    pub fn doc_mut(&mut self) -> &mut Document {
+        &mut self.documents[self.active]
     }
 
-    pub fn undo(&mut self) {
+    // The file tree, made the first time it is needed.
+    pub fn explorer_mut(&mut self) -> &mut Explorer {
+        self.explorer.get_or_insert_with(|| Explorer::new(env::current_dir().unwrap_or_else(|_| PathBuf::from("."))))
+    }
 
-        if let Some(state) = self.undo_stack.pop() {
-            self.redo_stack.push(EditorState {
-                content: self.content.clone(),  
-                cursor_row: self.cursor_row,
-                cursor_col: self.cursor_col,    
-            });
+    pub fn show_message(&mut self, text: impl Into<String>) {
+        self.status = Some(StatusMessage { text: text.into(), is_error: false });
+    }
 
-            self.content = state.content;   
-            self.cursor_row = state.cursor_row;
-            self.cursor_col = state.cursor_col;
-            self.modified = true;
-        }
+    pub fn show_error(&mut self, text: impl Into<String>) {
+        self.status = Some(StatusMessage { text: text.into(), is_error: true });
     }
 
-    pub fn redo(&mut self) {
-        if let Some(state) = self.redo_stack.pop() {
-            self.undo_stack.push(EditorState {
-                content: self.content.clone(),
-                cursor_row: self.cursor_row,
-                cursor_col: self.cursor_col,
-            });
-            self.content = state.content;
-            self.cursor_row = state.cursor_row;
-            self.cursor_col = state.cursor_col;
-            self.modified = true;
-        }
+    // Makes `filename` the active buffer, opening it unless it already is.
+    // Returns the buffer's index and whether the file was newly loaded.
+    pub fn open(&mut self, filename: &str) -> io::Result<(usize, bool)> {
+        let path = fs::canonicalize(filename).ok();
+        let open = self.documents.iter().position(|d| {
+            d.filename == filename || path.is_some() && fs::canonicalize(&d.filename).ok() == path
+        });
+        let index = match open {
+            Some(index) => index,
+            None => {
+                let mut document = Document::load(filename)?;
+                document.soft_wrap = self.soft_wrap;
+                if self.watch {
+                    let watch = FileWatch::new(Path::new(filename)).unwrap_or_else(|e| {
+                        self.show_error(format!("Cannot watch '{}' ({}), polling it instead", filename, e));
+                        FileWatch::polling()
+                    });
+                    document.watch = Some(watch);
+                }
+                // The blank buffer made when only a directory was given on
+                // the command line gives way to the first file opened.
+                if self.documents.len() == 1 && self.documents[0].is_blank() {
+                    self.documents[0] = document;
+                    0
+                } else {
+                    self.documents.push(document);
+                    self.documents.len() - 1
+                }
+            }
+        };
+        self.switch_to(index);
+        Ok((index, open.is_none()))
     }
 
+    // Makes a new buffer without a file holding `bytes`, and shows it.
+    pub fn open_unnamed(&mut self, bytes: Vec<u8>) -> usize {
+        let mut document = Document::unnamed(bytes);
+        document.soft_wrap = self.soft_wrap;
+        self.documents.push(document);
+        let index = self.documents.len() - 1;
+        self.switch_to(index);
+        index
+    }
 
-    pub fn adjust_scroll(&mut self, viewport_height: usize) {
-        if self.cursor_row < self.scroll_offset {
-            self.scroll_offset = self.cursor_row;
-        } else if self.cursor_row >= self.scroll_offset + viewport_height {
-            self.scroll_offset = self.cursor_row - viewport_height + 1;
+    // Shows buffer `index` in the focused pane.
+    pub fn switch_to(&mut self, index: usize) {
+        if index != self.active && self.active < self.documents.len() {
+            self.doc_mut().break_undo_group();
         }
+        self.active = index;
+        self.panes[self.focus].document = index;
+    }
+
+    pub fn cycle_buffer(&mut self, forward: bool) {
+        let count = self.documents.len();
+        let index = if forward { (self.active + 1) % count } else { (self.active + count - 1) % count };
+        self.switch_to(index);
     }
 
-    pub fn search(&mut self) {
-        self.search_matches.clear();
-        if self.search_query.is_empty() {
+    // Closes the active buffer, dropping any unsaved changes. Panes showing
+    // it move on to a neighbouring buffer. Closing the last one quits.
+    pub fn close_buffer(&mut self) {
+        if self.documents.len() == 1 {
+            self.exit = true;
             return;
         }
-        for (row, line) in self.content.iter().enumerate() {
-            let mut start = 0;
-            while let Some(col) = line[start..].find(&self.search_query) {
-                self.search_matches.push((row, start + col));
-                start += col + 1;
+        let closing = self.active;
+        let mut document = self.documents.remove(closing);
+        document.close();
+
+        let replacement = closing.min(self.documents.len() - 1);
+        for (i, pane) in self.panes.iter_mut().enumerate() {
+            if pane.document == closing {
+                pane.document = replacement;
+                if i != self.focus {
+                    pane.view = self.documents[replacement].view();
+                }
+            } else if pane.document > closing {
+                pane.document -= 1;
             }
         }
-        self.search_index = 0;
+        self.active = self.panes[self.focus].document;
     }
 
-    pub fn next_match(&mut self) {
-        if !self.search_matches.is_empty() {
-
-            self.search_index = (self.search_index + 1) % self.search_matches.len();
-            let (row, col) = self.search_matches[self.search_index];
-            
-            self.cursor_row = row;
-            self.cursor_col = col;
-
+    // Moves the focus to `pane`, handing the view of the pane losing it back
+    // to that pane.
+    pub fn focus_pane(&mut self, pane: usize) {
+        if pane == self.focus {
+            return;
         }
+        self.doc_mut().break_undo_group();
+        self.panes[self.focus].view = self.doc().view();
+        self.focus = pane;
+        self.active = self.panes[pane].document;
+        let view = self.panes[pane].view;
+        self.doc_mut().set_view(view);
     }
 
+    pub fn cycle_pane(&mut self, forward: bool) {
+        let order = self.layout.panes();
+        let count = order.len();
+        let position = order.iter().position(|&p| p == self.focus).unwrap_or(0);
+        let next = if forward { (position + 1) % count } else { (position + count - 1) % count };
+        self.focus_pane(order[next]);
+    }
 
-    pub fn prev_match(&mut self) {
-        if !self.search_matches.is_empty() {
-            self.search_index = if self.search_index == 0 {
-
-                self.search_matches.len() - 1   
-            } else {
+    // Splits the focused pane in two, both showing the same buffer from the
+    // same place. The new pane gets the focus.
+    pub fn split_pane(&mut self, orientation: Orientation) {
+        self.panes.push(Pane::new(self.active, self.doc().view()));
+        let new = self.panes.len() - 1;
+        self.layout.split(self.focus, new, orientation);
+        self.focus_pane(new);
+    }
 
-                self.search_index - 1
-            };
-            let (row, col) = self.search_matches[self.search_index];
-            self.cursor_row = row;  
-            self.cursor_col = col;  
+    // Closes the focused pane unless it is the only one. The buffer it showed
+    // stays open.
+    pub fn close_pane(&mut self) {
+        if self.panes.len() == 1 {
+            return;
+        }
+        let closing = self.focus;
+        self.cycle_pane(false);
+        self.layout.remove(closing);
+        self.panes.remove(closing);
+        if self.focus > closing {
+            self.focus -= 1;
         }
     }
 
-    pub fn start_selection(&mut self) {
-
-        self.selection = Some(Selection {
-
-            start_row: self.cursor_row, 
-
-            start_col: self.cursor_col,
-
-            end_row: self.cursor_row,
-            
-            end_col: self.cursor_col,
-
-        });
+    pub fn resize_pane(&mut self, delta: i16) {
+        self.layout.resize(self.focus, delta);
     }
 
-    pub fn update_selection(&mut self) {
-
-        if let Some(ref mut sel) = self.selection {
-            sel.end_row = self.cursor_row;
-            sel.end_col = self.cursor_col;
-
+    // Runs `f` on the document shown in `pane`, seen through that pane's view.
+    pub fn with_view(&mut self, pane: usize, f: impl FnOnce(&mut Document)) {
+        if pane == self.focus {
+            f(self.doc_mut());
+            return;
         }
-        
+        let Pane { document, view, .. } = self.panes[pane];
+        let document = &mut self.documents[document];
+        let own = document.view();
+        document.set_view(view);
+        f(document);
+        self.panes[pane].view = document.view();
+        document.set_view(own);
     }
 
-    pub fn clear_selection(&mut self) { self.selection = None; }
-
-    pub fn get_selected_text(&self) -> String {
-
-        if let Some(sel) = self.selection {
-            let (start_row, start_col, end_row, end_col) = self.normalize_selection(sel);
-            
-            if start_row == end_row {
-                self.content[start_row][start_col..end_col].to_string()
-            } else {
-                let mut result = self.content[start_row][start_col..].to_string();
-                result.push('\n');
-                for row in (start_row + 1)..end_row {
-                    result.push_str(&self.content[row]);
-                    result.push('\n');
-                }
-                result.push_str(&self.content[end_row][..end_col]);
-                result
+    // Points the buffers of files at or under `from` to where they were
+    // renamed to.
+    pub fn renamed(&mut self, from: &Path, to: &Path) {
+        for document in &mut self.documents {
+            let Ok(path) = path::absolute(&document.filename) else { continue };
+            let Ok(rest) = path.strip_prefix(from) else { continue };
+            let new = to.join(rest);
+            swap::remove(&document.filename);
+            swap::remember(&document.filename, None);
+            document.filename = self.explorer.as_ref().map_or_else(|| new.display().to_string(), |e| e.relative(&new));
+            document.swap_revision = None;
+            if document.watch.is_some() {
+                document.watch = Some(FileWatch::new(&new).unwrap_or_else(|_| FileWatch::polling()));
             }
-
-        } else {
-            return String::new()
         }
     }
 
-    pub fn normalize_selection(&self, sel: Selection) -> (usize, usize, usize, usize) {
-        if sel.start_row < sel.end_row || (sel.start_row == sel.end_row && sel.start_col <= sel.end_col) {
-            (sel.start_row, sel.start_col, sel.end_row, sel.end_col)
-        } else {
-            (sel.end_row, sel.end_col, sel.start_row, sel.start_col)
-        }
+    pub fn modified_buffers(&self) -> Vec<&str> {
+        self.documents.iter().filter(|d| d.modified).map(|d| d.label()).collect()
     }
 
-    pub fn delete_selection(&mut self) {
-        if let Some(sel) = self.selection {
-            self.save_state();
-            let (start_row, start_col, end_row, end_col) = self.normalize_selection(sel);
-            
-            if start_row == end_row {
-                self.content[start_row].replace_range(start_col..end_col, "");
-            } else {
-                let end_part = self.content[end_row][end_col..].to_string();
-                self.content[start_row].truncate(start_col);
-                self.content[start_row].push_str(&end_part);
-                
-                for _ in (start_row + 1)..=end_row {
-                    self.content.remove(start_row + 1);
-                }
-            }
-
-            self.cursor_row = start_row;
-            self.cursor_col = start_col;
-            self.selection = None;
-            self.modified = true;
-
+    // The buffers whose names fuzzily match what was typed in the buffer
+    // list, best match first. All of them, in order, when nothing was typed.
+    pub fn buffer_matches(&self) -> Vec<usize> {
+        if self.input_buffer.is_empty() {
+            return (0..self.documents.len()).collect();
         }
+        let matcher = SkimMatcherV2::default();
+        let mut matches: Vec<(i64, usize)> = self.documents.iter().enumerate()
+            .filter_map(|(i, d)| Some((matcher.fuzzy_match(d.label(), &self.input_buffer)?, i)))
+            .collect();
+        matches.sort_by_key(|&(score, i)| (-score, i));
+        matches.into_iter().map(|(_, i)| i).collect()
     }
-}
\ No newline at end of file
+}
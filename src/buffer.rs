@@ -0,0 +1,215 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+
+// Text storage for a document. Positions are (row, col) pairs where col is a
+// byte offset into the line, lines never include their trailing '\n', and
+// there is always at least one (possibly empty) line. Cloning is cheap since
+// the underlying rope shares its nodes.
+#[derive(Clone)]
+pub struct Buffer {
+    rope: Rope,
+    revision: u64,
+    line_ending: LineEnding,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LineEnding {
+    Lf,
+    Crlf,
+    // Both kinds appear. The buffer then keeps every '\r' as part of the line
+    // so the file is written back byte for byte, and lines split on '\n' only.
+    // The cursor never goes past a '\r' at the end of a line.
+    Mixed,
+}
+
+impl LineEnding {
+    pub fn detect(text: &str) -> LineEnding {
+        let crlf = text.matches("\r\n").count();
+        let lf = text.matches('\n').count() - crlf;
+        match (lf, crlf) {
+            (_, 0) => LineEnding::Lf,
+            (0, _) => LineEnding::Crlf,
+            _ => LineEnding::Mixed,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Crlf => "\r\n",
+            LineEnding::Lf | LineEnding::Mixed => "\n",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+            LineEnding::Mixed => "Mixed",
+        }
+    }
+}
+
+// Revisions are unique across all buffers, so a cache keyed on one can never
+// mistake a replaced buffer for the one it was built from.
+static NEXT_REVISION: AtomicU64 = AtomicU64::new(1);
+
+fn next_revision() -> u64 {
+    NEXT_REVISION.fetch_add(1, Ordering::Relaxed)
+}
+
+impl Buffer {
+    pub fn new() -> Buffer {
+        Buffer { rope: Rope::new(), revision: next_revision(), line_ending: LineEnding::Lf }
+    }
+
+    // Builds a buffer from file contents using `ending` line endings. A single
+    // final line ending is dropped; whether it was there is tracked separately.
+    pub fn from_text(text: &str, ending: LineEnding) -> Buffer {
+        let text = text.strip_suffix(ending.as_str()).unwrap_or(text);
+        let text: Cow<str> = if ending == LineEnding::Crlf {
+            Cow::Owned(text.replace("\r\n", "\n"))
+        } else {
+            Cow::Borrowed(text)
+        };
+
+        Buffer { rope: Rope::from_str(&text), revision: next_revision(), line_ending: ending }
+    }
+
+    // The line endings the buffer is written with.
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    pub fn set_line_ending(&mut self, ending: LineEnding) {
+        self.line_ending = ending;
+        self.revision = next_revision();
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn text(&self) -> String {
+        self.rope.to_string()
+    }
+
+    pub fn len_lines(&self) -> usize {
+        self.rope.len_lines()
+    }
+
+    pub fn line(&self, row: usize) -> Cow<'_, str> {
+        let line = self.rope.line(row);
+        let len = line.len_chars();
+        if len > 0 && line.char(len - 1) == '\n' {
+            line.slice(..len - 1).into()
+        } else {
+            line.into()
+        }
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = Cow<'_, str>> {
+        (0..self.len_lines()).map(|row| self.line(row))
+    }
+
+    pub fn line_len(&self, row: usize) -> usize {
+        if row >= self.len_lines() {
+            return 0;
+        }
+        let start = self.rope.line_to_byte(row);
+        let end = if row + 1 < self.len_lines() {
+            self.rope.line_to_byte(row + 1) - 1
+        } else {
+            self.rope.len_bytes()
+        };
+        end - start
+    }
+
+    fn char_idx(&self, row: usize, col: usize) -> usize {
+        self.rope.byte_to_char(self.rope.line_to_byte(row) + col)
+    }
+
+    pub fn insert(&mut self, row: usize, col: usize, text: &str) {
+        let idx = self.char_idx(row, col);
+        self.rope.insert(idx, text);
+        self.revision = next_revision();
+    }
+
+    pub fn remove(&mut self, start_row: usize, start_col: usize, end_row: usize, end_col: usize) -> String {
+        let start = self.char_idx(start_row, start_col);
+        let end = self.char_idx(end_row, end_col);
+        let removed = self.rope.slice(start..end).to_string();
+        self.rope.remove(start..end);
+        self.revision = next_revision();
+        removed
+    }
+
+    pub fn slice(&self, start_row: usize, start_col: usize, end_row: usize, end_col: usize) -> String {
+        let start = self.char_idx(start_row, start_col);
+        let end = self.char_idx(end_row, end_col);
+        self.rope.slice(start..end).to_string()
+    }
+
+    // Position just past `text` if it were inserted at (row, col).
+    pub fn end_of_insert(row: usize, col: usize, text: &str) -> (usize, usize) {
+        match text.rfind('\n') {
+            Some(i) => (row + text.matches('\n').count(), text.len() - i - 1),
+            None => (row, col + text.len()),
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        for chunk in self.rope.chunks() {
+            if self.line_ending == LineEnding::Crlf {
+                for (i, part) in chunk.split('\n').enumerate() {
+                    if i > 0 {
+                        writer.write_all(b"\r\n")?;
+                    }
+                    writer.write_all(part.as_bytes())?;
+                }
+            } else {
+                writer.write_all(chunk.as_bytes())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_line_endings() {
+        assert_eq!(LineEnding::detect(""), LineEnding::Lf);
+        assert_eq!(LineEnding::detect("a\nb\n"), LineEnding::Lf);
+        assert_eq!(LineEnding::detect("a\r\nb\r\n"), LineEnding::Crlf);
+        assert_eq!(LineEnding::detect("a\r\nb\n"), LineEnding::Mixed);
+        assert_eq!(LineEnding::detect("a\rb"), LineEnding::Lf);
+    }
+
+    #[test]
+    fn splits_lines_without_their_endings() {
+        let buffer = Buffer::from_text("a\r\nbc\r\n", LineEnding::Crlf);
+        assert_eq!(buffer.len_lines(), 2);
+        assert_eq!(buffer.line(0), "a");
+        assert_eq!(buffer.line(1), "bc");
+        assert_eq!(buffer.line_len(1), 2);
+
+        let buffer = Buffer::from_text("a\r\nb\n", LineEnding::Mixed);
+        assert_eq!(buffer.line(0), "a\r");
+        assert_eq!(buffer.line(1), "b");
+    }
+
+    #[test]
+    fn writes_the_line_ending_it_was_switched_to() {
+        let mut buffer = Buffer::from_text("a\nb\n", LineEnding::Lf);
+        buffer.set_line_ending(LineEnding::Crlf);
+        let mut bytes = Vec::new();
+        buffer.write_to(&mut bytes).unwrap();
+        assert_eq!(bytes, b"a\r\nb");
+    }
+}
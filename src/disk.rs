@@ -0,0 +1,56 @@
+use std::fs::{self, Metadata};
+use std::io;
+use std::time::SystemTime;
+
+use crate::undo_cache;
+
+// What the file looked like when it was last read or written, so that changes
+// made by other programs can be noticed. The hash decides; size and mtime
+// only save re-reading the file when they haven't moved.
+#[derive(Clone, Copy, PartialEq)]
+pub struct DiskState {
+    pub modified: Option<SystemTime>,
+    pub len: u64,
+    pub hash: u64,
+}
+
+impl DiskState {
+    fn new(metadata: &Metadata, bytes: &[u8]) -> DiskState {
+        DiskState { modified: metadata.modified().ok(), len: metadata.len(), hash: undo_cache::hash(bytes) }
+    }
+
+    fn matches(&self, metadata: &Metadata) -> bool {
+        self.len == metadata.len() && self.modified.is_some() && self.modified == metadata.modified().ok()
+    }
+}
+
+// The metadata is taken before reading so that a write racing with the read
+// shows up as a change on the next check rather than going unnoticed.
+pub fn read(path: &str) -> io::Result<(Vec<u8>, DiskState)> {
+    let metadata = fs::metadata(path)?;
+    let bytes = fs::read(path)?;
+    let state = DiskState::new(&metadata, &bytes);
+    Ok((bytes, state))
+}
+
+// The state of a file that was just written with `bytes`.
+pub fn written(path: &str, bytes: &[u8]) -> io::Result<DiskState> {
+    Ok(DiskState::new(&fs::metadata(path)?, bytes))
+}
+
+// The file's state now, or None if it doesn't exist. It is only read again
+// when its size or mtime differ from `known`.
+pub fn current(path: &str, known: Option<DiskState>) -> io::Result<Option<DiskState>> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if let Some(known) = known
+        && known.matches(&metadata)
+    {
+        return Ok(Some(known));
+    }
+    let bytes = fs::read(path)?;
+    Ok(Some(DiskState::new(&metadata, &bytes)))
+}
@@ -0,0 +1,1035 @@
+use std::cell::RefCell;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use encoding_rs::{Encoding, UTF_8};
+use rustix::fs::Access;
+use serde::{Deserialize, Serialize};
+use similar::{DiffTag, TextDiff};
+
+use crate::buffer::{Buffer, LineEnding};
+use crate::disk::{self, DiskState};
+use crate::encoding;
+use crate::history::{CursorState, Edit, EditKind, History};
+use crate::render_cache::WrapCache;
+use crate::save;
+use crate::swap::{self, Snapshot, Swap};
+use crate::text;
+use crate::undo_cache;
+use crate::watch::FileWatch;
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Selection {
+    pub start_row: usize,
+    pub start_col: usize,
+    pub end_row: usize,
+    pub end_col: usize,
+}
+
+// Where a document is looked at from. The document holds the view of the
+// focused pane it is shown in, other panes keep theirs until they get focus.
+#[derive(Clone, Copy, Default)]
+pub struct View {
+    pub cursor_row: usize,
+    pub cursor_col: usize,
+    pub scroll_offset: usize,
+    pub h_scroll_offset: usize,
+    pub scroll_sub_row: usize,
+    pub soft_wrap: bool,
+    pub wrap_width: usize,
+    pub selection: Option<Selection>,
+}
+
+// How often the swap file is rewritten while editing.
+const SWAP_INTERVAL: Duration = Duration::from_secs(4);
+
+// A file open in the editor: its text and undo history along with the
+// cursor, scroll position and selection it is viewed with.
+pub struct Document {
+    // Empty for a buffer that hasn't been saved to a file yet.
+    pub filename: String,
+    pub content: Buffer,
+    pub cursor_row: usize,
+    pub cursor_col: usize,
+    pub modified: bool,
+    // The file as last read or written, None if it doesn't exist yet.
+    pub disk: Option<DiskState>,
+    // The change on disk the user was last told about, as `disk_now` returned it.
+    pub disk_reported: Option<Option<u64>>,
+    // Set in watch mode, where a clean buffer follows the file as it changes.
+    pub watch: Option<FileWatch>,
+    // The buffer revision last written to the swap file, and when.
+    pub swap_revision: Option<u64>,
+    pub swap_written: Instant,
+    pub final_newline: bool,
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+    // Set for files with NUL bytes in them until the user agrees to edit anyway.
+    pub binary_warning: bool,
+    // Set while the buffer is only being looked at: nothing that would
+    // change it or its file is allowed.
+    pub read_only: bool,
+    pub scroll_offset: usize,
+    pub h_scroll_offset: usize,
+    pub soft_wrap: bool,
+    pub wrap_width: usize,
+    // Visual rows of line `scroll_offset` scrolled past in soft-wrap mode.
+    pub scroll_sub_row: usize,
+    pub wrap_cache: RefCell<WrapCache>,
+    pub history: History,
+    pub search_query: String,
+    pub search_matches: Vec<(usize, usize)>,
+    pub search_index: usize,
+    pub selection: Option<Selection>,
+}
+
+impl Document {
+    pub fn new(filename: String) -> Document {
+        Document {
+            filename,
+            content: Buffer::new(),
+            cursor_row: 0,
+            cursor_col: 0,
+            modified: false,
+            disk: None,
+            disk_reported: None,
+            watch: None,
+            swap_revision: None,
+            swap_written: Instant::now(),
+            final_newline: true,
+            encoding: UTF_8,
+            bom: false,
+            binary_warning: false,
+            read_only: false,
+            scroll_offset: 0,
+            h_scroll_offset: 0,
+            soft_wrap: false,
+            wrap_width: 80,
+            scroll_sub_row: 0,
+            wrap_cache: RefCell::new(WrapCache::default()),
+            history: History::default(),
+            search_query: String::new(),
+            search_matches: Vec::new(),
+            search_index: 0,
+            selection: None,
+        }
+    }
+
+    // Opens `filename` along with the undo history kept for it. A file that
+    // doesn't exist yet starts out empty and modified, and one that can't be
+    // written to starts out read-only.
+    pub fn load(filename: &str) -> io::Result<Document> {
+        let mut document = Document::new(filename.to_string());
+        match document.open(None) {
+            Ok(()) => {
+                document.read_only = rustix::fs::access(filename, Access::WRITE_OK).is_err();
+                if let Some(disk) = document.disk
+                    && let Some(history) = undo_cache::load(filename, disk.hash, document.encoding.name())
+                {
+                    document.history = history;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => document.modified = true,
+            Err(e) => return Err(e),
+        }
+        Ok(document)
+    }
+
+    // A buffer with no file behind it, holding `bytes` as if they had been
+    // read from one. It gets a name when it is first saved, and with nothing
+    // in it, it is written like a new file.
+    pub fn unnamed(bytes: Vec<u8>) -> Document {
+        let mut document = Document::new(String::new());
+        if !bytes.is_empty() {
+            document.content = document.decode(bytes, None);
+        }
+        document
+    }
+
+    // Reads the file into the buffer, detecting its encoding unless one is given.
+    pub fn open(&mut self, encoding: Option<&'static Encoding>) -> io::Result<()> {
+        let (bytes, state) = disk::read(&self.filename)?;
+        self.content = self.decode(bytes, encoding);
+        self.disk = Some(state);
+        self.disk_reported = None;
+        Ok(())
+    }
+
+    // Takes on the encoding, line endings and so on of file contents and
+    // returns the buffer they make.
+    fn decode(&mut self, bytes: Vec<u8>, encoding: Option<&'static Encoding>) -> Buffer {
+        let decoded = match encoding {
+            Some(encoding) => encoding::decode_with(&bytes, encoding),
+            None => encoding::decode(bytes),
+        };
+        self.encoding = decoded.encoding;
+        self.bom = decoded.bom;
+        self.binary_warning = decoded.text.contains('\0');
+        self.final_newline = decoded.text.ends_with('\n');
+        Buffer::from_text(&decoded.text, LineEnding::detect(&decoded.text))
+    }
+
+    // The file as it should be written, before encoding.
+    fn file_text(&self) -> io::Result<String> {
+        let mut text = Vec::new();
+        self.content.write_to(&mut text)?;
+        if self.final_newline {
+            text.extend_from_slice(self.line_ending().as_str().as_bytes());
+        }
+        String::from_utf8(text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn file_bytes(&self) -> io::Result<Vec<u8>> {
+        encoding::encode(&self.file_text()?, self.encoding, self.bom)
+    }
+
+    // Encodes before touching the file so a failure leaves it intact. Returns
+    // the number of bytes written.
+    pub fn save(&mut self) -> io::Result<usize> {
+        let bytes = self.file_bytes()?;
+        save::write(Path::new(&self.filename), &bytes)?;
+        self.modified = false;
+        self.history.mark_saved();
+        let state = disk::written(&self.filename, &bytes)?;
+        self.disk = Some(state);
+        self.disk_reported = None;
+        let _ = undo_cache::save(&self.filename, state.hash, self.encoding.name(), &self.history);
+        Ok(bytes.len())
+    }
+
+    // Reads the file again as `encoding`. Unsaved changes are dropped, and so
+    // is the undo history since it describes the text as it was decoded before.
+    pub fn reopen_with_encoding(&mut self, encoding: &'static Encoding) -> io::Result<()> {
+        self.open(Some(encoding))?;
+        self.history = History::default();
+        self.modified = false;
+        self.selection = None;
+        self.search_matches.clear();
+        self.cursor_row = self.cursor_row.min(self.content.len_lines() - 1);
+        self.cursor_col = 0;
+        Ok(())
+    }
+
+    // The hash of what is on disk now, or None if the file is gone. When the
+    // file was only touched the recorded state is brought up to date. Files
+    // that can't be read are left alone.
+    fn disk_now(&mut self) -> Option<Option<u64>> {
+        let now = disk::current(&self.filename, self.disk).ok()?;
+        if now.map(|s| s.hash) == self.disk.map(|s| s.hash) {
+            self.disk = now;
+        }
+        Some(now.map(|s| s.hash))
+    }
+
+    // Whether another program changed or removed the file since it was last
+    // read or written.
+    pub fn disk_changed(&mut self) -> bool {
+        self.disk_now().is_some_and(|now| now != self.disk.map(|s| s.hash))
+    }
+
+    // Like `disk_changed`, but reports each change only once so that
+    // dismissing the prompt about it doesn't bring it straight back.
+    pub fn new_disk_change(&mut self) -> bool {
+        let Some(now) = self.disk_now() else { return false };
+        if now == self.disk.map(|s| s.hash) || self.disk_reported == Some(now) {
+            return false;
+        }
+        self.disk_reported = Some(now);
+        true
+    }
+
+    // Replaces the buffer with what is on disk, as a single undo step so that
+    // unsaved changes it overwrites can be got back.
+    pub fn reload(&mut self) -> io::Result<()> {
+        let (bytes, state) = disk::read(&self.filename)?;
+        let new = self.decode(bytes, Some(self.encoding));
+        self.begin_transaction();
+        self.set_line_ending(new.line_ending());
+        self.replace_text(&new.text());
+        self.end_transaction();
+        self.history.mark_saved();
+        self.modified = false;
+        self.disk = Some(state);
+        self.disk_reported = None;
+        Ok(())
+    }
+
+    // Changes the buffer to hold `new`, touching only the lines that differ,
+    // as one undo step. The cursor stays on the same row and column where it
+    // can.
+    fn replace_text(&mut self, new: &str) {
+        let old = self.content.text();
+        let display_col = text::display_col(&self.content.line(self.cursor_row), self.cursor_col);
+        let row = self.cursor_row;
+
+        let diff = TextDiff::from_lines(old.as_str(), new);
+        self.selection = None;
+        self.begin_transaction();
+        // Going front to back, the rows above `at` already hold the new text
+        // and the rest of the old text follows them.
+        let mut at = 0;
+        for op in diff.ops() {
+            let (tag, old_lines, new_lines) = op.as_tag_tuple();
+            if tag == DiffTag::Equal {
+                at += old_lines.len();
+                continue;
+            }
+            let (start_row, start_col) = self.row_start(at);
+            let (end_row, end_col) = self.row_start(at + old_lines.len());
+            if !old_lines.is_empty() {
+                self.remove(start_row, start_col, end_row, end_col);
+            }
+            (self.cursor_row, self.cursor_col) = (start_row, start_col);
+            at += new_lines.len();
+            let inserted = diff.new_slices()[new_lines].concat();
+            if !inserted.is_empty() {
+                self.insert_text(&inserted);
+            }
+        }
+        self.end_transaction();
+
+        self.cursor_row = row.min(self.content.len_lines() - 1);
+        self.cursor_col = text::col_at_display(&self.content.line(self.cursor_row), display_col);
+        self.scroll_offset = self.scroll_offset.min(self.cursor_row);
+        self.scroll_sub_row = 0;
+        self.search_matches.clear();
+    }
+
+    // Where diff line `row` starts in the buffer. Lines past the last row
+    // start at the end of the buffer.
+    fn row_start(&self, row: usize) -> (usize, usize) {
+        let last = self.content.len_lines() - 1;
+        if row <= last { (row, 0) } else { (last, self.content.line_len(last)) }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            filename: self.filename.clone(),
+            content: self.content.clone(),
+            encoding: self.encoding,
+            bom: self.bom,
+            final_newline: self.final_newline,
+            cursor_row: self.cursor_row,
+            cursor_col: self.cursor_col,
+        }
+    }
+
+    // Keeps the swap file in step with the buffer: rewritten at most every
+    // SWAP_INTERVAL while there are unsaved changes, removed once there are none.
+    pub fn update_swap(&mut self) {
+        if self.filename.is_empty() {
+            return;
+        }
+        if !self.modified {
+            if self.swap_revision.take().is_some() {
+                swap::remove(&self.filename);
+            }
+            swap::remember(&self.filename, None);
+            return;
+        }
+
+        let snapshot = self.snapshot();
+        let revision = self.content.revision();
+        if self.swap_revision != Some(revision) && self.swap_written.elapsed() >= SWAP_INTERVAL {
+            swap::write_later(self.snapshot());
+            self.swap_revision = Some(revision);
+            self.swap_written = Instant::now();
+        }
+        swap::remember(&self.filename, Some(snapshot));
+    }
+
+    // The file name without its directory, as shown on tabs.
+    pub fn name(&self) -> String {
+        Path::new(self.label()).file_name().map_or(self.label().to_string(), |name| name.to_string_lossy().into_owned())
+    }
+
+    // The file name, or what stands in for it while there is none.
+    pub fn label(&self) -> &str {
+        if self.filename.is_empty() { "[No Name]" } else { &self.filename }
+    }
+
+    // Whether this is an untouched buffer with no file and nothing in it.
+    pub fn is_blank(&self) -> bool {
+        self.filename.is_empty() && !self.modified && self.content.len_lines() == 1 && self.line_len(0) == 0
+    }
+
+    // Called when the buffer goes away. Unsaved changes are dropped, so the
+    // swap file goes too, and the undo history is kept if it still matches the
+    // file on disk.
+    pub fn close(&mut self) {
+        if self.filename.is_empty() {
+            return;
+        }
+        if !self.disk_changed() && let Some(disk) = self.disk {
+            self.history.drop_empty();
+            let _ = undo_cache::save(&self.filename, disk.hash, self.encoding.name(), &self.history);
+        }
+        swap::remove(&self.filename);
+        swap::remember(&self.filename, None);
+    }
+
+    // Brings back the unsaved changes from a swap file as one undo step on
+    // top of the file as it is on disk.
+    pub fn recover(&mut self, swap: Swap) {
+        self.encoding = Encoding::for_label(swap.encoding.as_bytes()).unwrap_or(self.encoding);
+        self.bom = swap.bom;
+        self.final_newline = swap.final_newline;
+        self.begin_transaction();
+        self.set_line_ending(swap.line_ending);
+        self.replace_text(&swap.text);
+        self.end_transaction();
+        (self.cursor_row, self.cursor_col) = self.clamp(swap.cursor_row, swap.cursor_col);
+        self.modified = true;
+    }
+
+    // The nearest position to (row, col) that is inside the buffer.
+    fn clamp(&self, row: usize, col: usize) -> (usize, usize) {
+        let row = row.min(self.content.len_lines() - 1);
+        let col = col.min(self.line_len(row));
+        if self.content.line(row).is_char_boundary(col) { (row, col) } else { (row, 0) }
+    }
+
+    pub fn view(&self) -> View {
+        View {
+            cursor_row: self.cursor_row,
+            cursor_col: self.cursor_col,
+            scroll_offset: self.scroll_offset,
+            h_scroll_offset: self.h_scroll_offset,
+            scroll_sub_row: self.scroll_sub_row,
+            soft_wrap: self.soft_wrap,
+            wrap_width: self.wrap_width,
+            selection: self.selection,
+        }
+    }
+
+    // Takes on `view`, which may have been made before the text last
+    // changed, so positions past the end are pulled back in.
+    pub fn set_view(&mut self, view: View) {
+        (self.cursor_row, self.cursor_col) = self.clamp(view.cursor_row, view.cursor_col);
+        self.scroll_offset = view.scroll_offset.min(self.cursor_row);
+        self.h_scroll_offset = view.h_scroll_offset;
+        self.scroll_sub_row = if self.scroll_offset == view.scroll_offset { view.scroll_sub_row } else { 0 };
+        self.soft_wrap = view.soft_wrap;
+        self.wrap_width = view.wrap_width;
+        self.clamp_scroll_sub_row();
+        self.selection = view.selection.map(|sel| {
+            let (start_row, start_col) = self.clamp(sel.start_row, sel.start_col);
+            let (end_row, end_col) = self.clamp(sel.end_row, sel.end_col);
+            Selection { start_row, start_col, end_row, end_col }
+        });
+    }
+
+    // A unified diff from the file on disk to the buffer, one line per entry.
+    pub fn disk_diff(&self) -> io::Result<Vec<String>> {
+        let on_disk = match fs::read(&self.filename) {
+            Ok(bytes) => encoding::decode_with(&bytes, self.encoding).text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        let in_editor = self.file_text()?;
+        let diff = TextDiff::from_lines(&on_disk, &in_editor);
+        let unified = diff.unified_diff().header("on disk", "in editor").to_string();
+        Ok(unified.lines().map(|line| line.trim_end_matches('\r').to_string()).collect())
+    }
+
+    fn cursor_state(&self) -> CursorState {
+        CursorState { row: self.cursor_row, col: self.cursor_col, selection: self.selection }
+    }
+
+    fn restore_cursor_state(&mut self, state: CursorState) {
+        self.cursor_row = state.row;
+        self.cursor_col = state.col;
+        self.selection = state.selection;
+    }
+
+    // Starts a new undo step, or keeps extending the current one when `kind`
+    // continues a run of typing or deleting at the cursor.
+    pub fn begin_edit(&mut self, kind: EditKind) {
+        self.history.begin(kind, self.cursor_state());
+    }
+
+    pub fn break_undo_group(&mut self) {
+        self.history.seal();
+    }
+
+    // Everything edited between these two calls is undone as a single step.
+    pub fn begin_transaction(&mut self) {
+        self.history.begin_transaction(self.cursor_state());
+    }
+
+    pub fn end_transaction(&mut self) {
+        self.history.end_transaction();
+    }
+
+    fn record(&mut self, edit: Edit) {
+        self.history.record(edit, self.cursor_state());
+        self.modified = true;
+    }
+
+    pub fn undo(&mut self) {
+        if let Some(state) = self.history.undo(&mut self.content) {
+            self.restore_cursor_state(state);
+            self.modified = self.disk.is_none() || !self.history.is_saved();
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if let Some(state) = self.history.redo(&mut self.content) {
+            self.restore_cursor_state(state);
+            self.modified = self.disk.is_none() || !self.history.is_saved();
+        }
+    }
+
+    pub fn switch_branch(&mut self, forward: bool) {
+        if let Some(state) = self.history.switch_branch(&mut self.content, forward) {
+            self.restore_cursor_state(state);
+            self.modified = self.disk.is_none() || !self.history.is_saved();
+        }
+    }
+
+    pub fn jump_to_history(&mut self, node: usize) {
+        if let Some(state) = self.history.jump_to(&mut self.content, node) {
+            self.restore_cursor_state(state);
+            self.modified = self.disk.is_none() || !self.history.is_saved();
+        }
+    }
+
+
+    pub fn line_number_width(&self) -> usize {
+        self.content.len_lines().to_string().len().max(2)
+    }
+
+    // Columns taken by the line number gutter, " 42 │ ".
+    pub fn gutter_width(&self) -> usize {
+        self.line_number_width() + 4
+    }
+
+    pub fn adjust_scroll(&mut self, viewport_height: usize, viewport_width: usize, side_margin: usize) {
+        if self.soft_wrap {
+            // One column is kept free so the cursor fits after a full row.
+            self.wrap_width = viewport_width.saturating_sub(1).max(1);
+            self.h_scroll_offset = 0;
+            self.adjust_wrapped_scroll(viewport_height);
+            return;
+        }
+
+        if self.cursor_row < self.scroll_offset {
+            self.scroll_offset = self.cursor_row;
+        } else if self.cursor_row >= self.scroll_offset + viewport_height {
+            self.scroll_offset = self.cursor_row - viewport_height + 1;
+        }
+
+        // Keep `side_margin` columns visible on either side of the cursor where
+        // the viewport is wide enough for it.
+        let margin = side_margin.min(viewport_width.saturating_sub(1) / 2);
+        let cursor = text::display_col(&self.content.line(self.cursor_row), self.cursor_col);
+        if cursor < self.h_scroll_offset + margin {
+            self.h_scroll_offset = cursor.saturating_sub(margin);
+        } else if cursor + margin >= self.h_scroll_offset + viewport_width {
+            self.h_scroll_offset = cursor + margin + 1 - viewport_width;
+        }
+    }
+
+    pub fn wrap_points(&self, row: usize) -> Vec<usize> {
+        self.wrap_points_at(row, self.wrap_width)
+    }
+
+    pub fn wrap_points_at(&self, row: usize, width: usize) -> Vec<usize> {
+        let line = self.content.line(row);
+        self.wrap_cache.borrow_mut().wrap_points(&line, width, || text::wrap_points(&line, width))
+    }
+
+    fn visual_row_above(&self, row: usize, sub_row: usize) -> Option<(usize, usize)> {
+        if sub_row > 0 {
+            Some((row, sub_row - 1))
+        } else if row > 0 {
+            Some((row - 1, self.wrap_points(row - 1).len() - 1))
+        } else {
+            None
+        }
+    }
+
+    fn visual_row_below(&self, row: usize, sub_row: usize) -> Option<(usize, usize)> {
+        if sub_row + 1 < self.wrap_points(row).len() {
+            Some((row, sub_row + 1))
+        } else if row + 1 < self.content.len_lines() {
+            Some((row + 1, 0))
+        } else {
+            None
+        }
+    }
+
+    fn cursor_visual_row(&self) -> usize {
+        text::wrap_row(&self.wrap_points(self.cursor_row), self.cursor_col)
+    }
+
+    // A new wrap width, or an edit to the top line from another pane, can
+    // leave it with fewer visual rows than were scrolled past.
+    fn clamp_scroll_sub_row(&mut self) {
+        let rows = self.wrap_points(self.scroll_offset).len();
+        self.scroll_sub_row = self.scroll_sub_row.min(rows - 1);
+    }
+
+    fn adjust_wrapped_scroll(&mut self, viewport_height: usize) {
+        self.clamp_scroll_sub_row();
+        let cursor = (self.cursor_row, self.cursor_visual_row());
+        let top = (self.scroll_offset, self.scroll_sub_row);
+        if cursor < top {
+            (self.scroll_offset, self.scroll_sub_row) = cursor;
+            return;
+        }
+
+        // Walk up from the cursor; if the top of the viewport isn't reached
+        // within a screenful, scroll so the cursor ends up on the last row.
+        let mut position = cursor;
+        for _ in 1..viewport_height.max(1) {
+            if position == top {
+                return;
+            }
+            match self.visual_row_above(position.0, position.1) {
+                Some(above) => position = above,
+                None => break,
+            }
+        }
+        if position > top {
+            (self.scroll_offset, self.scroll_sub_row) = position;
+        }
+    }
+
+    // The column on visual row `sub_row` of `row` closest to display column `x`
+    // of that row.
+    fn col_in_visual_row(&self, row: usize, sub_row: usize, x: usize) -> usize {
+        let line = self.content.line(row);
+        let points = self.wrap_points(row);
+        let start = points[sub_row];
+        let end = points.get(sub_row + 1).copied().unwrap_or(self.line_len(row));
+        let col = start + text::col_at_display(&line[start..end], x);
+        if col == end && sub_row + 1 < points.len() {
+            text::prev_boundary(&line, end)
+        } else {
+            col
+        }
+    }
+
+    fn move_visual(&mut self, count: usize, down: bool) {
+        let line = self.content.line(self.cursor_row);
+        let points = self.wrap_points(self.cursor_row);
+        let sub_row = text::wrap_row(&points, self.cursor_col);
+        let x = text::display_col(&line[points[sub_row]..], self.cursor_col - points[sub_row]);
+
+        let mut position = (self.cursor_row, sub_row);
+        for _ in 0..count {
+            let next = if down {
+                self.visual_row_below(position.0, position.1)
+            } else {
+                self.visual_row_above(position.0, position.1)
+            };
+            match next {
+                Some(next) => position = next,
+                None => break,
+            }
+        }
+        self.cursor_row = position.0;
+        self.cursor_col = self.col_in_visual_row(position.0, position.1, x);
+    }
+
+    pub fn move_up(&mut self, count: usize) {
+        if self.soft_wrap {
+            self.move_visual(count, false);
+        } else {
+            self.move_to_row(self.cursor_row.saturating_sub(count));
+        }
+    }
+
+    pub fn move_down(&mut self, count: usize) {
+        if self.soft_wrap {
+            self.move_visual(count, true);
+        } else {
+            self.move_to_row((self.cursor_row + count).min(self.content.len_lines() - 1));
+        }
+    }
+
+    // The buffer position shown at `screen_row` rows and `x` columns into the
+    // text area, if that row holds a line.
+    pub fn position_at(&self, screen_row: usize, x: usize) -> Option<(usize, usize)> {
+        if !self.soft_wrap {
+            let row = self.scroll_offset + screen_row;
+            if row >= self.content.len_lines() {
+                return None;
+            }
+            let col = text::col_at_display(&self.content.line(row), x + self.h_scroll_offset);
+            return Some((row, col.min(self.line_len(row))));
+        }
+
+        let mut position = (self.scroll_offset, self.scroll_sub_row);
+        for _ in 0..screen_row {
+            position = self.visual_row_below(position.0, position.1)?;
+        }
+        Some((position.0, self.col_in_visual_row(position.0, position.1, x)))
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.content.line_ending()
+    }
+
+    fn set_line_ending(&mut self, ending: LineEnding) {
+        let from = self.line_ending();
+        if from != ending {
+            self.content.set_line_ending(ending);
+            self.record(Edit::LineEnding { from, to: ending });
+        }
+    }
+
+    // Switches LF files to CRLF and back, as an undo step. Switching straight
+    // back undoes that step, so a buffer that was saved before is again. Mixed
+    // files are normalised to LF, which strips the carriage returns kept at
+    // the end of their lines.
+    pub fn convert_line_endings(&mut self) {
+        let to = match self.line_ending() {
+            LineEnding::Lf => LineEnding::Crlf,
+            LineEnding::Crlf | LineEnding::Mixed => LineEnding::Lf,
+        };
+        if let [Edit::LineEnding { from, .. }] = self.history.current_edits()
+            && *from == to
+        {
+            self.undo();
+            return;
+        }
+
+        let cursor = (self.cursor_row, self.cursor_col);
+        self.begin_transaction();
+        for row in 0..self.content.len_lines() {
+            let len = self.line_len(row);
+            if len < self.content.line_len(row) {
+                self.remove(row, len, row, len + 1);
+            }
+        }
+        self.set_line_ending(to);
+        self.end_transaction();
+        self.cursor_row = cursor.0;
+        self.cursor_col = cursor.1.min(self.line_len(cursor.0));
+    }
+
+    pub fn toggle_soft_wrap(&mut self) {
+        self.soft_wrap = !self.soft_wrap;
+        self.scroll_sub_row = 0;
+        self.h_scroll_offset = 0;
+    }
+
+    pub fn search(&mut self) {
+        self.search_matches.clear();
+        if self.search_query.is_empty() {
+            return;
+        }
+        for (row, line) in self.content.lines().enumerate() {
+            let mut start = 0;
+            while let Some(col) = line[start..].find(&self.search_query) {
+                self.search_matches.push((row, start + col));
+                start = text::next_boundary(&line, start + col);
+            }
+        }
+        self.search_index = 0;
+    }
+
+    pub fn next_match(&mut self) {
+        if !self.search_matches.is_empty() {
+
+            self.search_index = (self.search_index + 1) % self.search_matches.len();
+            let (row, col) = self.search_matches[self.search_index];
+            
+            self.cursor_row = row;
+            self.cursor_col = col;
+
+        }
+    }
+
+
+    pub fn prev_match(&mut self) {
+        if !self.search_matches.is_empty() {
+            self.search_index = if self.search_index == 0 {
+
+                self.search_matches.len() - 1   
+            } else {
+
+                self.search_index - 1
+            };
+            let (row, col) = self.search_matches[self.search_index];
+            self.cursor_row = row;  
+            self.cursor_col = col;  
+        }
+    }
+
+    pub fn start_selection(&mut self) {
+
+        self.selection = Some(Selection {
+
+            start_row: self.cursor_row, 
+
+            start_col: self.cursor_col,
+
+            end_row: self.cursor_row,
+            
+            end_col: self.cursor_col,
+
+        });
+    }
+
+    pub fn update_selection(&mut self) {
+
+        if let Some(ref mut sel) = self.selection {
+            sel.end_row = self.cursor_row;
+            sel.end_col = self.cursor_col;
+
+        }
+        
+    }
+
+    pub fn clear_selection(&mut self) { self.selection = None; }
+
+    pub fn get_selected_text(&self) -> String {
+
+        if let Some(sel) = self.selection {
+            let (start_row, start_col, end_row, end_col) = self.normalize_selection(sel);
+            self.content.slice(start_row, start_col, end_row, end_col)
+        } else {
+            String::new()
+        }
+    }
+
+    pub fn normalize_selection(&self, sel: Selection) -> (usize, usize, usize, usize) {
+        if sel.start_row < sel.end_row || (sel.start_row == sel.end_row && sel.start_col <= sel.end_col) {
+            (sel.start_row, sel.start_col, sel.end_row, sel.end_col)
+        } else {
+            (sel.end_row, sel.end_col, sel.start_row, sel.start_col)
+        }
+    }
+
+    pub fn delete_selection(&mut self) {
+        if self.selection.is_some() {
+            self.begin_edit(EditKind::Other);
+            self.remove_selection();
+        }
+    }
+
+    // Starts a `kind` edit that first takes away the selection, if there is
+    // one, so that typing over a selection is undone in one step.
+    pub fn begin_replacing_selection(&mut self, kind: EditKind) {
+        self.begin_edit(kind);
+        self.remove_selection();
+    }
+
+    fn remove_selection(&mut self) {
+        if let Some(sel) = self.selection {
+            let (start_row, start_col, end_row, end_col) = self.normalize_selection(sel);
+            self.selection = None;
+            self.remove(start_row, start_col, end_row, end_col);
+        }
+    }
+
+    // Where the text of `row` ends. A '\r' left at the end of a line in a
+    // Mixed file is part of its line ending, so the cursor stays before it.
+    pub fn line_len(&self, row: usize) -> usize {
+        let len = self.content.line_len(row);
+        if self.line_ending() == LineEnding::Mixed && self.content.line(row).ends_with('\r') {
+            len - 1
+        } else {
+            len
+        }
+    }
+
+    pub fn insert_text(&mut self, text: &str) {
+        let (row, col) = (self.cursor_row, self.cursor_col);
+        self.content.insert(row, col, text);
+        (self.cursor_row, self.cursor_col) = Buffer::end_of_insert(row, col, text);
+        self.record(Edit::Insert { row, col, text: text.to_string() });
+    }
+
+    fn remove(&mut self, start_row: usize, start_col: usize, end_row: usize, end_col: usize) {
+        let text = self.content.remove(start_row, start_col, end_row, end_col);
+        self.cursor_row = start_row;
+        self.cursor_col = start_col;
+        self.record(Edit::Delete { row: start_row, col: start_col, text });
+    }
+
+    pub fn move_left(&mut self) {
+        if self.cursor_col > 0 {
+            self.cursor_col = text::prev_boundary(&self.content.line(self.cursor_row), self.cursor_col);
+        } else if self.cursor_row > 0 {
+            self.cursor_row -= 1;
+            self.cursor_col = self.line_len(self.cursor_row);
+        }
+    }
+
+    pub fn move_right(&mut self) {
+        if self.cursor_col < self.line_len(self.cursor_row) {
+            self.cursor_col = text::next_boundary(&self.content.line(self.cursor_row), self.cursor_col);
+        } else if self.cursor_row + 1 < self.content.len_lines() {
+            self.cursor_row += 1;
+            self.cursor_col = 0;
+        }
+    }
+
+    // Puts the cursor on 1-based `line` and character `column`, or as near
+    // to them as the buffer goes.
+    pub fn go_to(&mut self, line: usize, column: usize) {
+        let row = line.saturating_sub(1).min(self.content.len_lines() - 1);
+        let text = self.content.line(row);
+        self.cursor_row = row;
+        let end = self.line_len(row);
+        self.cursor_col = text.char_indices().nth(column.saturating_sub(1)).map_or(end, |(col, _)| col.min(end));
+    }
+
+    // Moves to another row, keeping the cursor in the same on-screen column.
+    pub fn move_to_row(&mut self, row: usize) {
+        let display_col = text::display_col(&self.content.line(self.cursor_row), self.cursor_col);
+        self.cursor_row = row;
+        self.cursor_col = text::col_at_display(&self.content.line(row), display_col).min(self.line_len(row));
+    }
+
+    pub fn backspace(&mut self) {
+        if self.cursor_col > 0 {
+            let prev = text::prev_boundary(&self.content.line(self.cursor_row), self.cursor_col);
+            self.remove(self.cursor_row, prev, self.cursor_row, self.cursor_col);
+        } else if self.cursor_row > 0 {
+            let prev_len = self.line_len(self.cursor_row - 1);
+            self.remove(self.cursor_row - 1, prev_len, self.cursor_row, 0);
+        }
+    }
+
+    pub fn delete_forward(&mut self) {
+        if self.cursor_col < self.line_len(self.cursor_row) {
+            let next = text::next_boundary(&self.content.line(self.cursor_row), self.cursor_col);
+            self.remove(self.cursor_row, self.cursor_col, self.cursor_row, next);
+        } else if self.cursor_row + 1 < self.content.len_lines() {
+            self.remove(self.cursor_row, self.cursor_col, self.cursor_row + 1, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::{env, process};
+
+    use super::*;
+
+    // A file of its own for each test, holding `bytes`.
+    fn temp_file(name: &str, bytes: &[u8]) -> String {
+        let path = env::temp_dir().join(format!("oxide-{}-{}", process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        path.display().to_string()
+    }
+
+    fn document(text: &str) -> Document {
+        let mut document = Document::new(String::new());
+        document.content.insert(0, 0, text);
+        document
+    }
+
+    // Every text of up to three lines made of "a", "b" and empty lines, with
+    // and without a final newline.
+    fn small_texts() -> Vec<String> {
+        let mut lists: Vec<Vec<&str>> = vec![Vec::new()];
+        let mut longest = lists.clone();
+        for _ in 0..3 {
+            longest = longest.iter().flat_map(|l| ["a", "b", ""].map(|line| [l.as_slice(), &[line]].concat())).collect();
+            lists.extend(longest.iter().cloned());
+        }
+        lists.iter().flat_map(|l| [l.join("\n"), l.join("\n") + "\n"]).collect()
+    }
+
+    #[test]
+    fn replaces_text_with_the_new_text() {
+        let mut doc = document("b\n\n");
+        doc.replace_text("\n\na");
+        assert_eq!(doc.content.text(), "\n\na");
+
+        for old in small_texts() {
+            for new in small_texts() {
+                let mut doc = document(&old);
+                doc.replace_text(&new);
+                assert_eq!(doc.content.text(), new, "replacing {:?}", old);
+                doc.undo();
+                assert_eq!(doc.content.text(), old, "undoing the change to {:?}", new);
+            }
+        }
+    }
+
+    fn recover(disk: &str, text: &str, line_ending: LineEnding) -> Document {
+        let filename = temp_file("recover", disk.as_bytes());
+        let mut doc = Document::load(&filename).unwrap();
+        doc.recover(Swap {
+            path: PathBuf::from(&filename),
+            pid: 0,
+            encoding: "UTF-8".to_string(),
+            bom: false,
+            line_ending,
+            final_newline: false,
+            cursor_row: 2,
+            cursor_col: 1,
+            text: text.to_string(),
+        });
+        fs::remove_file(&filename).unwrap();
+        doc
+    }
+
+    #[test]
+    fn recovers_exactly_what_the_swap_file_holds() {
+        let doc = recover("b\n\n", "\n\na", LineEnding::Crlf);
+        assert_eq!(doc.content.text(), "\n\na");
+        assert_eq!(doc.file_bytes().unwrap(), b"\r\n\r\na");
+        assert!(doc.modified);
+
+        for (disk, text) in small_texts().iter().zip(small_texts().iter().rev()) {
+            assert_eq!(recover(disk, text, LineEnding::Lf).content.text(), *text, "recovering over {:?}", disk);
+        }
+    }
+
+    #[test]
+    fn saves_files_back_unchanged() {
+        let texts = ["", "\n", "a", "a\nb\n", "a\nb", "a\r\nb\r\n", "a\r\nb", "a\r\nb\nc\r\n", "a\r\nb\nc", "\r\n\r\n"];
+        let filename = temp_file("unchanged", b"");
+        for text in texts {
+            fs::write(&filename, text).unwrap();
+            let doc = Document::load(&filename).unwrap();
+            assert_eq!(doc.file_bytes().unwrap(), text.as_bytes(), "saving {:?}", text);
+        }
+        fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn ends_new_files_with_a_newline() {
+        let filename = env::temp_dir().join(format!("oxide-{}-new", process::id())).display().to_string();
+        let mut doc = Document::load(&filename).unwrap();
+        doc.insert_text("a");
+        assert_eq!(doc.file_bytes().unwrap(), b"a\n");
+    }
+
+    #[test]
+    fn reloads_exactly_what_is_on_disk() {
+        let filename = temp_file("reload", b"b\n\n");
+        let mut doc = Document::load(&filename).unwrap();
+        for text in ["\n\na", "b\n\n"].into_iter().chain(small_texts().iter().map(String::as_str)) {
+            fs::write(&filename, text).unwrap();
+            doc.reload().unwrap();
+            assert_eq!(doc.file_bytes().unwrap(), text.as_bytes(), "reloading {:?}", text);
+            assert!(!doc.modified);
+        }
+        fs::remove_file(&filename).unwrap();
+    }
+
+    #[test]
+    fn undoes_typing_over_a_selection_in_one_step() {
+        let mut doc = document("hello world");
+        doc.selection = Some(Selection { start_row: 0, start_col: 0, end_row: 0, end_col: 5 });
+        for c in ["b", "y", "e"] {
+            doc.begin_replacing_selection(EditKind::Insert);
+            doc.insert_text(c);
+        }
+        assert_eq!(doc.content.text(), "bye world");
+        doc.undo();
+        assert_eq!(doc.content.text(), "hello world");
+    }
+}
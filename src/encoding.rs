@@ -0,0 +1,230 @@
+use std::io;
+
+use chardetng::EncodingDetector;
+use encoding_rs::{DecoderResult, Encoding, UTF_8, UTF_16BE, UTF_16LE};
+
+pub struct Decoded {
+    pub text: String,
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+}
+
+// Bytes that don't decode are kept in the text as one character each from the
+// end of the last private use plane, U+10FF00 + byte, and turned back into the
+// same byte on save. A file that really contains one of these characters has
+// the bytes it was encoded as escaped the same way, so it is written back as
+// it was.
+const ESCAPE_BASE: u32 = 0x10FF00;
+
+pub fn escape(byte: u8) -> char {
+    char::from_u32(ESCAPE_BASE + byte as u32).unwrap()
+}
+
+pub fn escaped_byte(c: char) -> Option<u8> {
+    (c as u32).checked_sub(ESCAPE_BASE).and_then(|b| u8::try_from(b).ok())
+}
+
+// Picks the encoding from a byte order mark if there is one, then UTF-8 if the
+// bytes are valid UTF-8, and otherwise whatever legacy encoding they most
+// resemble.
+pub fn decode(bytes: Vec<u8>) -> Decoded {
+    if let Some((encoding, _)) = Encoding::for_bom(&bytes) {
+        return decode_with(&bytes, encoding);
+    }
+    let bytes = match String::from_utf8(bytes) {
+        Ok(mut text) => {
+            escape_reserved(&mut text, 0, UTF_8);
+            return Decoded { text, encoding: UTF_8, bom: false };
+        }
+        Err(e) => e.into_bytes(),
+    };
+
+    // Binary files, and UTF-8 text with a few broken bytes in it, are read as
+    // UTF-8 with the bad bytes escaped rather than guessed at.
+    if bytes.contains(&0) || bytes.utf8_chunks().any(|chunk| !chunk.valid().is_ascii()) {
+        return decode_with(&bytes, UTF_8);
+    }
+
+    let mut detector = EncodingDetector::new();
+    detector.feed(&bytes, true);
+    decode_with(&bytes, detector.guess(None, true))
+}
+
+pub fn decode_with(bytes: &[u8], encoding: &'static Encoding) -> Decoded {
+    let (bytes, bom) = match Encoding::for_bom(bytes) {
+        Some((bom_encoding, len)) if bom_encoding == encoding => (&bytes[len..], true),
+        _ => (bytes, false),
+    };
+
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut text = String::new();
+    let mut read = 0;
+    loop {
+        let remaining = bytes.len() - read;
+        text.reserve(decoder.max_utf8_buffer_length_without_replacement(remaining).unwrap_or(remaining) + 16);
+        let start = text.len();
+        let (result, n) = decoder.decode_to_string_without_replacement(&bytes[read..], &mut text, true);
+        escape_reserved(&mut text, start, encoding);
+        read += n;
+        match result {
+            DecoderResult::InputEmpty => break,
+            DecoderResult::OutputFull => {}
+            DecoderResult::Malformed(len, consumed_after) => {
+                let end = read - consumed_after as usize;
+                text.extend(bytes[end - len as usize..end].iter().map(|&b| escape(b)));
+            }
+        }
+    }
+    Decoded { text, encoding, bom }
+}
+
+// Swaps characters from the escape range that were really in the file, in
+// `text` from `start` on, for escapes of the bytes they were encoded as.
+fn escape_reserved(text: &mut String, start: usize, encoding: &'static Encoding) {
+    if !text[start..].contains(|c| escaped_byte(c).is_some()) {
+        return;
+    }
+    let decoded = text.split_off(start);
+    for c in decoded.chars() {
+        if escaped_byte(c).is_none() {
+            text.push(c);
+            continue;
+        }
+        let mut bytes = Vec::new();
+        let _ = encode_run(c.encode_utf8(&mut [0; 4]), encoding, &mut bytes);
+        text.extend(bytes.into_iter().map(escape));
+    }
+}
+
+// Looks up an encoding by any of its WHATWG labels ("latin1", "utf-16be",
+// "shift_jis", ...). A "-bom" suffix asks for a byte order mark on save.
+// Encodings that can only be read, not written, are refused.
+pub fn for_label(label: &str) -> Option<(&'static Encoding, bool)> {
+    let label = label.trim().to_ascii_lowercase();
+    let (label, bom) = match label.strip_suffix("-bom") {
+        Some(label) => (label, true),
+        None => (label.as_str(), false),
+    };
+    let encoding = Encoding::for_label(label.as_bytes())?;
+    let utf16 = encoding == UTF_16LE || encoding == UTF_16BE;
+    if encoding.output_encoding() != encoding && !utf16 {
+        return None;
+    }
+    if bom && encoding != UTF_8 && !utf16 {
+        return None;
+    }
+    // UTF-16 without a BOM is rarely what anyone wants to write.
+    Some((encoding, bom || utf16))
+}
+
+// Fails rather than writing a lossy file when `text` has characters the
+// encoding cannot represent. Escaped bytes are written out as they were read.
+pub fn encode(text: &str, encoding: &'static Encoding, bom: bool) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() + 3);
+    if bom {
+        bytes.extend_from_slice(match encoding {
+            e if e == UTF_16LE => b"\xFF\xFE",
+            e if e == UTF_16BE => b"\xFE\xFF",
+            e if e == UTF_8 => b"\xEF\xBB\xBF",
+            _ => b"",
+        });
+    }
+
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if let Some(byte) = escaped_byte(c) {
+            encode_run(&text[start..i], encoding, &mut bytes)?;
+            bytes.push(byte);
+            start = i + c.len_utf8();
+        }
+    }
+    encode_run(&text[start..], encoding, &mut bytes)?;
+    Ok(bytes)
+}
+
+fn encode_run(text: &str, encoding: &'static Encoding, bytes: &mut Vec<u8>) -> io::Result<()> {
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        for unit in text.encode_utf16() {
+            bytes.extend_from_slice(&if encoding == UTF_16LE { unit.to_le_bytes() } else { unit.to_be_bytes() });
+        }
+        return Ok(());
+    }
+
+    let (encoded, _, had_errors) = encoding.encode(text);
+    if had_errors {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("text contains characters that {} cannot represent", encoding.name()),
+        ));
+    }
+    bytes.extend_from_slice(&encoded);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_and_writes_utf16_with_a_bom() {
+        for (encoding, bytes) in [
+            (UTF_16LE, b"\xFF\xFEh\x00\xE9\x00\n\x00".as_slice()),
+            (UTF_16BE, b"\xFE\xFF\x00h\x00\xE9\x00\n".as_slice()),
+        ] {
+            let decoded = decode(bytes.to_vec());
+            assert_eq!(decoded.text, "hé\n");
+            assert_eq!(decoded.encoding, encoding);
+            assert!(decoded.bom);
+            assert_eq!(encode(&decoded.text, decoded.encoding, decoded.bom).unwrap(), bytes);
+        }
+    }
+
+    #[test]
+    fn reads_plain_utf8_without_a_bom() {
+        let decoded = decode("hé\n".as_bytes().to_vec());
+        assert_eq!(decoded.text, "hé\n");
+        assert_eq!(decoded.encoding, UTF_8);
+        assert!(!decoded.bom);
+    }
+
+    #[test]
+    fn keeps_bytes_that_dont_decode() {
+        let bytes = b"ok \xFF\xFE\xC3 \xC3\xA9\n".to_vec();
+        let decoded = decode(bytes.clone());
+        assert_eq!(decoded.encoding, UTF_8);
+        assert_eq!(decoded.text, format!("ok {}{}{} é\n", escape(0xFF), escape(0xFE), escape(0xC3)));
+        assert_eq!(encode(&decoded.text, decoded.encoding, decoded.bom).unwrap(), bytes);
+
+        // Characters from the escape range itself come back as they were.
+        for bytes in ["a\u{10FF41}b\n".as_bytes().to_vec(), b"\xF4\x8F\xBD\x81 \xFF".to_vec()] {
+            let decoded = decode(bytes.clone());
+            assert_eq!(encode(&decoded.text, decoded.encoding, decoded.bom).unwrap(), bytes);
+        }
+        let utf16 = b"\xFF\xFE\xFF\xDB\x41\xDF".to_vec();
+        let decoded = decode(utf16.clone());
+        assert_eq!(encode(&decoded.text, decoded.encoding, decoded.bom).unwrap(), utf16);
+    }
+
+    #[test]
+    fn keeps_binary_files_byte_for_byte() {
+        let bytes: Vec<u8> = (0..=255).collect();
+        let decoded = decode(bytes.clone());
+        assert_eq!(decoded.encoding, UTF_8);
+        assert_eq!(encode(&decoded.text, decoded.encoding, decoded.bom).unwrap(), bytes);
+    }
+
+    #[test]
+    fn escapes_every_byte_reversibly() {
+        for byte in 0..=255 {
+            assert_eq!(escaped_byte(escape(byte)), Some(byte));
+        }
+        assert_eq!(escaped_byte('a'), None);
+    }
+
+    #[test]
+    fn refuses_characters_the_encoding_lacks() {
+        let (latin1, _) = for_label("latin1").unwrap();
+        assert_eq!(encode("é", latin1, false).unwrap(), b"\xE9");
+        assert!(encode("→", latin1, false).is_err());
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use ignore::WalkBuilder;
+
+pub struct Entry {
+    pub path: PathBuf,
+    pub name: String,
+    pub depth: usize,
+    pub is_dir: bool,
+    pub expanded: bool,
+}
+
+// The file tree shown in the sidebar, rooted at the working directory.
+// `entries` is the tree as it is drawn: every entry of the root followed by
+// the contents of each expanded directory right below it. Files ignored by
+// .gitignore and the like are left out.
+pub struct Explorer {
+    pub root: PathBuf,
+    pub entries: Vec<Entry>,
+    pub selected: usize,
+    pub scroll: usize,
+    expanded: HashSet<PathBuf>,
+}
+
+impl Explorer {
+    pub fn new(root: PathBuf) -> Explorer {
+        let mut explorer = Explorer { root, entries: Vec::new(), selected: 0, scroll: 0, expanded: HashSet::new() };
+        explorer.refresh();
+        explorer
+    }
+
+    // Lists the tree again, keeping the selection on the same path where
+    // it still exists.
+    pub fn refresh(&mut self) {
+        let selected = self.selected().map(|entry| entry.path.clone());
+        self.entries.clear();
+        let root = self.root.clone();
+        self.list(&root, 0);
+        if let Some(path) = selected {
+            self.select(&path);
+        }
+        self.selected = self.selected.min(self.entries.len().saturating_sub(1));
+    }
+
+    fn list(&mut self, dir: &Path, depth: usize) {
+        let walk = WalkBuilder::new(dir)
+            .max_depth(Some(1))
+            .hidden(false)
+            .require_git(false)
+            .filter_entry(|entry| entry.file_name() != ".git")
+            .build();
+        let mut children: Vec<(bool, String, PathBuf)> = walk
+            .filter_map(Result::ok)
+            .filter(|entry| entry.depth() == 1)
+            .map(|entry| {
+                let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                (!is_dir, entry.file_name().to_string_lossy().into_owned(), entry.into_path())
+            })
+            .collect();
+        children.sort();
+
+        for (is_file, name, path) in children {
+            let expanded = !is_file && self.expanded.contains(&path);
+            self.entries.push(Entry { path: path.clone(), name, depth, is_dir: !is_file, expanded });
+            if expanded {
+                self.list(&path, depth + 1);
+            }
+        }
+    }
+
+    pub fn selected(&self) -> Option<&Entry> {
+        self.entries.get(self.selected)
+    }
+
+    pub fn select(&mut self, path: &Path) {
+        if let Some(index) = self.entries.iter().position(|entry| entry.path == path) {
+            self.selected = index;
+        }
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        let last = self.entries.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    pub fn set_expanded(&mut self, expanded: bool) {
+        let Some(entry) = self.selected().filter(|entry| entry.is_dir) else { return };
+        let path = entry.path.clone();
+        if expanded {
+            self.expanded.insert(path);
+        } else {
+            self.expanded.remove(&path);
+        }
+        self.refresh();
+    }
+
+    pub fn toggle(&mut self) {
+        let expanded = self.selected().is_some_and(|entry| entry.expanded);
+        self.set_expanded(!expanded);
+    }
+
+    // Moves the selection to the directory holding the selected entry.
+    pub fn select_parent(&mut self) {
+        let Some(depth) = self.selected().map(|entry| entry.depth) else { return };
+        if let Some(index) = self.entries[..self.selected].iter().rposition(|entry| entry.depth < depth) {
+            self.selected = index;
+        }
+    }
+
+    // The directory new files go in: the selected one, or the one holding
+    // the selected file.
+    pub fn target_dir(&self) -> PathBuf {
+        match self.selected() {
+            Some(entry) if entry.is_dir => entry.path.clone(),
+            Some(entry) => entry.path.parent().unwrap_or(&self.root).to_path_buf(),
+            None => self.root.clone(),
+        }
+    }
+
+    // `path` as it should be shown and opened: relative to the working
+    // directory where it is inside it.
+    pub fn relative(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root).unwrap_or(path).display().to_string()
+    }
+
+    // Makes `name` in the target directory, as a directory if it ends with a
+    // slash. Missing directories on the way are made too. Returns the path.
+    pub fn create(&mut self, name: &str) -> io::Result<PathBuf> {
+        let dir = self.target_dir();
+        let path = dir.join(name);
+        if name.ends_with('/') {
+            fs::create_dir_all(&path)?;
+        } else {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+        }
+        self.expanded.insert(dir);
+        self.refresh();
+        self.select(&path);
+        Ok(path)
+    }
+
+    // Gives the selected entry a new name in the same directory. Returns
+    // the old and new paths.
+    pub fn rename(&mut self, name: &str) -> io::Result<(PathBuf, PathBuf)> {
+        let Some(entry) = self.selected() else { return Err(io::ErrorKind::NotFound.into()) };
+        let from = entry.path.clone();
+        let to = from.parent().unwrap_or(&self.root).join(name);
+        if to.exists() {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        fs::rename(&from, &to)?;
+        if self.expanded.remove(&from) {
+            self.expanded.insert(to.clone());
+        }
+        self.refresh();
+        self.select(&to);
+        Ok((from, to))
+    }
+
+    pub fn delete(&mut self) -> io::Result<PathBuf> {
+        let Some(entry) = self.selected() else { return Err(io::ErrorKind::NotFound.into()) };
+        let path = entry.path.clone();
+        if entry.is_dir {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+        self.refresh();
+        Ok(path)
+    }
+
+    // Scrolls just far enough to keep the selection on screen.
+    pub fn adjust_scroll(&mut self, height: usize) {
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if height > 0 && self.selected >= self.scroll + height {
+            self.scroll = self.selected + 1 - height;
+        }
+    }
+}
@@ -0,0 +1,163 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use ignore::WalkBuilder;
+
+// How much of the highlighted file is read for the preview.
+const PREVIEW_BYTES: u64 = 16 * 1024;
+
+// The files under a directory and the ones matching what has been typed so
+// far. The files are listed on a background thread, and the finder is kept
+// after it closes so that opening it again shows them straight away while
+// they are listed afresh.
+pub struct Finder {
+    root: PathBuf,
+    files: Vec<String>,
+    scan: Option<Receiver<Vec<String>>>,
+    query: String,
+    pub matches: Vec<usize>,
+    pub selected: usize,
+    pub preview: Vec<String>,
+}
+
+impl Finder {
+    // An empty `root` is the working directory.
+    pub fn new(root: PathBuf) -> Finder {
+        let mut finder = Finder {
+            root,
+            files: Vec::new(),
+            scan: None,
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+            preview: Vec::new(),
+        };
+        finder.rescan();
+        finder
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    // Clears the query for the finder being opened again, and starts listing
+    // the files again in case they changed.
+    pub fn reopen(&mut self) {
+        self.update("");
+        self.rescan();
+    }
+
+    fn rescan(&mut self) {
+        if self.scan.is_none() {
+            let (sender, receiver) = mpsc::channel();
+            let root = self.root.clone();
+            thread::spawn(move || sender.send(list_files(&root)));
+            self.scan = Some(receiver);
+        }
+    }
+
+    pub fn scanning(&self) -> bool {
+        self.scan.is_some()
+    }
+
+    // Takes in the files once they have been listed, keeping the same file
+    // highlighted.
+    pub fn poll(&mut self) {
+        let files = match self.scan.as_ref().map(Receiver::try_recv) {
+            Some(Ok(files)) => files,
+            Some(Err(TryRecvError::Disconnected)) => {
+                self.scan = None;
+                return;
+            }
+            _ => return,
+        };
+        self.scan = None;
+        let selected = self.matches.get(self.selected).map(|&index| self.files[index].clone());
+        self.files = files;
+        let query = self.query.clone();
+        self.update(&query);
+        if let Some(selected) = selected
+            && let Some(position) = self.matches.iter().position(|&index| self.files[index] == selected)
+        {
+            self.selected = position;
+            self.load_preview();
+        }
+    }
+
+    pub fn file(&self, index: usize) -> &str {
+        &self.files[index]
+    }
+
+    // Where the highlighted file is, as it should be opened.
+    pub fn selected_path(&self) -> Option<PathBuf> {
+        self.matches.get(self.selected).map(|&index| self.root.join(&self.files[index]))
+    }
+
+    // Ranks the files against `query`, best match first, and moves the
+    // selection back to the top. Shorter paths win a tie.
+    pub fn update(&mut self, query: &str) {
+        self.selected = 0;
+        self.query = query.to_string();
+        if query.is_empty() {
+            self.matches = (0..self.files.len()).collect();
+            self.load_preview();
+            return;
+        }
+        let matcher = SkimMatcherV2::default().ignore_case();
+        let mut matches: Vec<(i64, usize, usize)> = self.files.iter().enumerate()
+            .filter_map(|(i, file)| Some((matcher.fuzzy_match(file, query)?, file.len(), i)))
+            .collect();
+        matches.sort_by_key(|&(score, len, i)| (-score, len, i));
+        self.matches = matches.into_iter().map(|(_, _, i)| i).collect();
+        self.load_preview();
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        let last = self.matches.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+        self.load_preview();
+    }
+
+    fn load_preview(&mut self) {
+        self.preview = self.selected_path().map_or_else(Vec::new, |path| read_preview(&path));
+    }
+}
+
+// Every file under `root` by its path relative to it, sorted, leaving out
+// version control directories, the build directory at the top and whatever
+// .gitignore and the like ignore.
+fn list_files(root: &Path) -> Vec<String> {
+    let dir = if root.as_os_str().is_empty() { Path::new(".") } else { root };
+    let walk = WalkBuilder::new(dir)
+        .hidden(false)
+        .require_git(false)
+        .filter_entry(|entry| {
+            let top_target = entry.depth() == 1 && entry.file_name() == "target" && entry.file_type().is_some_and(|t| t.is_dir());
+            entry.file_name() != ".git" && !top_target
+        })
+        .build();
+    let mut files: Vec<String> = walk
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_some_and(|t| t.is_file()))
+        .map(|entry| entry.path().strip_prefix(dir).unwrap_or(entry.path()).display().to_string())
+        .collect();
+    files.sort();
+    files
+}
+
+// The start of `path`, as lines.
+fn read_preview(path: &Path) -> Vec<String> {
+    let mut bytes = Vec::new();
+    if let Err(e) = File::open(path).and_then(|file| file.take(PREVIEW_BYTES).read_to_end(&mut bytes)) {
+        return vec![format!("Cannot read: {}", e)];
+    }
+    if bytes.contains(&0) {
+        return vec!["Binary file".to_string()];
+    }
+    String::from_utf8_lossy(&bytes).lines().map(String::from).collect()
+}
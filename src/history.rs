@@ -0,0 +1,567 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::buffer::{Buffer, LineEnding};
+use crate::document::Selection;
+
+// A single primitive edit. `text` is what was inserted or removed at (row, col),
+// which is all that is needed to replay or revert it.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum Edit {
+    Insert { row: usize, col: usize, text: String },
+    Delete { row: usize, col: usize, text: String },
+    LineEnding { from: LineEnding, to: LineEnding },
+}
+
+impl Edit {
+    fn insert(buffer: &mut Buffer, row: usize, col: usize, text: &str) {
+        buffer.insert(row, col, text);
+    }
+
+    fn delete(buffer: &mut Buffer, row: usize, col: usize, text: &str) {
+        let (end_row, end_col) = Buffer::end_of_insert(row, col, text);
+        buffer.remove(row, col, end_row, end_col);
+    }
+
+    fn apply(&self, buffer: &mut Buffer) {
+        match self {
+            Edit::Insert { row, col, text } => Edit::insert(buffer, *row, *col, text),
+            Edit::Delete { row, col, text } => Edit::delete(buffer, *row, *col, text),
+            Edit::LineEnding { to, .. } => buffer.set_line_ending(*to),
+        }
+    }
+
+    fn revert(&self, buffer: &mut Buffer) {
+        match self {
+            Edit::Insert { row, col, text } => Edit::delete(buffer, *row, *col, text),
+            Edit::Delete { row, col, text } => Edit::insert(buffer, *row, *col, text),
+            Edit::LineEnding { from, .. } => buffer.set_line_ending(*from),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CursorState {
+    pub row: usize,
+    pub col: usize,
+    pub selection: Option<Selection>,
+}
+
+// Consecutive Insert or Delete steps at the cursor are merged into one undo
+// step as long as they arrive within this long of each other.
+const GROUP_TIMEOUT: Duration = Duration::from_millis(1000);
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum EditKind {
+    Insert,
+    Delete,
+    Other,
+}
+
+// One undo step: the edits it is made of plus where the cursor was on either side.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Change {
+    pub edits: Vec<Edit>,
+    pub kind: EditKind,
+    pub before: CursorState,
+    pub after: CursorState,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Node {
+    parent: usize,
+    children: Vec<usize>,
+    // The child that redo moves into; the most recently visited branch.
+    active_child: Option<usize>,
+    change: Change,
+    time: SystemTime,
+}
+
+pub struct TreeRow {
+    pub node: usize,
+    pub graph: String,
+}
+
+// Undo history kept as a tree. Node 0 is the state the file was opened in,
+// every other node is the change that leads to it from its parent. Editing
+// after an undo starts a new branch instead of discarding the old one.
+#[derive(Serialize, Deserialize)]
+pub struct History {
+    nodes: Vec<Node>,
+    current: usize,
+    // The node whose state matches the file on disk.
+    saved: usize,
+    #[serde(skip)]
+    sealed: bool,
+    #[serde(skip)]
+    transaction_depth: usize,
+    #[serde(skip)]
+    last_edit: Option<Instant>,
+}
+
+impl Default for History {
+    fn default() -> History {
+        let origin = CursorState { row: 0, col: 0, selection: None };
+        History {
+            nodes: vec![Node {
+                parent: 0,
+                children: Vec::new(),
+                active_child: None,
+                change: Change { edits: Vec::new(), kind: EditKind::Other, before: origin, after: origin },
+                time: SystemTime::now(),
+            }],
+            current: 0,
+            saved: 0,
+            sealed: false,
+            transaction_depth: 0,
+            last_edit: None,
+        }
+    }
+}
+
+impl History {
+    pub fn begin(&mut self, kind: EditKind, cursor: CursorState) {
+        if self.transaction_depth > 0 || self.can_extend(kind, cursor) {
+            return;
+        }
+        self.drop_empty();
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            parent: self.current,
+            children: Vec::new(),
+            active_child: None,
+            change: Change { edits: Vec::new(), kind, before: cursor, after: cursor },
+            time: SystemTime::now(),
+        });
+        self.nodes[self.current].children.push(id);
+        self.nodes[self.current].active_child = Some(id);
+        self.current = id;
+        self.sealed = false;
+    }
+
+    fn can_extend(&self, kind: EditKind, cursor: CursorState) -> bool {
+        let node = &self.nodes[self.current];
+        self.current != 0
+            && !self.sealed
+            && node.children.is_empty()
+            && kind != EditKind::Other
+            && node.change.kind == kind
+            && node.change.after == cursor
+            && self.last_edit.is_some_and(|at| at.elapsed() < GROUP_TIMEOUT)
+    }
+
+    // Stops the current step from absorbing further edits.
+    pub fn seal(&mut self) {
+        if self.transaction_depth == 0 {
+            self.sealed = true;
+        }
+    }
+
+    pub fn begin_transaction(&mut self, cursor: CursorState) {
+        self.begin(EditKind::Other, cursor);
+        self.transaction_depth += 1;
+    }
+
+    pub fn end_transaction(&mut self) {
+        self.transaction_depth = self.transaction_depth.saturating_sub(1);
+        self.seal();
+    }
+
+    pub fn record(&mut self, edit: Edit, cursor: CursorState) {
+        if self.current == 0 {
+            self.begin(EditKind::Other, cursor);
+        }
+        let node = &mut self.nodes[self.current];
+        node.change.edits.push(edit);
+        node.change.after = cursor;
+        node.time = SystemTime::now();
+        self.last_edit = Some(Instant::now());
+    }
+
+    fn step_up(&mut self, buffer: &mut Buffer) -> CursorState {
+        let node = &self.nodes[self.current];
+        for edit in node.change.edits.iter().rev() {
+            edit.revert(buffer);
+        }
+        let cursor = node.change.before;
+        let parent = node.parent;
+        self.nodes[parent].active_child = Some(self.current);
+        self.current = parent;
+        cursor
+    }
+
+    fn step_down(&mut self, buffer: &mut Buffer, child: usize) -> CursorState {
+        let node = &self.nodes[child];
+        for edit in &node.change.edits {
+            edit.apply(buffer);
+        }
+        self.nodes[self.current].active_child = Some(child);
+        self.current = child;
+        self.nodes[child].change.after
+    }
+
+    pub fn undo(&mut self, buffer: &mut Buffer) -> Option<CursorState> {
+        self.drop_empty();
+        self.sealed = true;
+        if self.current == 0 {
+            return None;
+        }
+        Some(self.step_up(buffer))
+    }
+
+    pub fn redo(&mut self, buffer: &mut Buffer) -> Option<CursorState> {
+        self.drop_empty();
+        self.sealed = true;
+        let child = self.nodes[self.current].active_child?;
+        Some(self.step_down(buffer, child))
+    }
+
+    // Moves to the previous or next sibling of the current state, i.e. the
+    // same point in history on another branch.
+    pub fn switch_branch(&mut self, buffer: &mut Buffer, forward: bool) -> Option<CursorState> {
+        self.drop_empty();
+        if self.current == 0 {
+            return None;
+        }
+        let siblings = &self.nodes[self.nodes[self.current].parent].children;
+        let index = siblings.iter().position(|&id| id == self.current)?;
+        let target = if forward { siblings.get(index + 1) } else { index.checked_sub(1).and_then(|i| siblings.get(i)) };
+        let target = *target?;
+        self.jump_to(buffer, target)
+    }
+
+    pub fn jump_to(&mut self, buffer: &mut Buffer, target: usize) -> Option<CursorState> {
+        self.drop_empty();
+        self.sealed = true;
+        if target == self.current || target >= self.nodes.len() {
+            return None;
+        }
+
+        let mut path = Vec::new();
+        let mut node = target;
+        while !self.is_ancestor(node, self.current) {
+            path.push(node);
+            node = self.nodes[node].parent;
+        }
+
+        let mut cursor = None;
+        while self.current != node {
+            cursor = Some(self.step_up(buffer));
+        }
+        for &child in path.iter().rev() {
+            cursor = Some(self.step_down(buffer, child));
+        }
+        cursor
+    }
+
+    fn is_ancestor(&self, ancestor: usize, mut node: usize) -> bool {
+        loop {
+            if node == ancestor {
+                return true;
+            }
+            if node == 0 {
+                return false;
+            }
+            node = self.nodes[node].parent;
+        }
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    // The edits of the step that led to the current state.
+    pub fn current_edits(&self) -> &[Edit] {
+        &self.nodes[self.current].change.edits
+    }
+
+    // Whether the buffer is back in the state that was last saved.
+    pub fn is_saved(&self) -> bool {
+        self.current == self.saved
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.drop_empty();
+        self.saved = self.current;
+    }
+
+    // A history read back from disk picks up at the state that was saved,
+    // since that is what the file on disk contains.
+    pub fn reopen(&mut self) {
+        self.current = self.saved;
+        self.sealed = true;
+    }
+
+    // Flattens the tree for display. The newest branch at each fork continues
+    // in the same column, older branches are drawn indented above it.
+    pub fn layout(&self) -> Vec<TreeRow> {
+        let mut rows = Vec::new();
+        self.layout_chain(0, String::new(), String::new(), &mut rows);
+        rows
+    }
+
+    fn layout_chain(&self, mut node: usize, mut first: String, rest: String, rows: &mut Vec<TreeRow>) {
+        loop {
+            rows.push(TreeRow { node, graph: first });
+            let children = &self.nodes[node].children;
+            let Some((&last, older)) = children.split_last() else { return };
+            for &child in older {
+                self.layout_chain(child, format!("{}├─ ", rest), format!("{}│  ", rest), rows);
+            }
+            first = rest.clone();
+            node = last;
+        }
+    }
+
+    pub fn describe(&self, node: usize) -> String {
+        if node == 0 {
+            return "original".to_string();
+        }
+        let edits = &self.nodes[node].change.edits;
+        let mut inserted = String::new();
+        let mut deleted = 0;
+        let mut ending = None;
+        for edit in edits {
+            match edit {
+                Edit::Insert { text, .. } => inserted.push_str(text),
+                Edit::Delete { text, .. } => deleted += text.chars().count(),
+                Edit::LineEnding { to, .. } => ending = Some(*to),
+            }
+        }
+        if let Some(ending) = ending
+            && inserted.is_empty()
+            && deleted == 0
+        {
+            return format!("{} line endings", ending.name());
+        }
+
+        let mut preview: String = inserted.chars().take(24).collect();
+        if preview.len() < inserted.len() {
+            preview.push('…');
+        }
+        match (inserted.is_empty(), deleted) {
+            (false, 0) => format!("+{:?}", preview),
+            (true, _) => format!("-{} chars", deleted),
+            (false, _) => format!("-{} +{:?}", deleted, preview),
+        }
+    }
+
+    pub fn age(&self, node: usize) -> String {
+        age(self.nodes[node].time)
+    }
+
+    // A step that was started but never received an edit is always the newest
+    // node and a leaf, so it can simply be popped. Called before the history
+    // is saved, too, so that no empty step is written out.
+    pub fn drop_empty(&mut self) {
+        let node = &self.nodes[self.current];
+        if self.current != 0
+            && self.current == self.nodes.len() - 1
+            && node.change.edits.is_empty()
+            && node.children.is_empty()
+        {
+            let parent = node.parent;
+            self.nodes.pop();
+            self.nodes[parent].children.pop();
+            self.nodes[parent].active_child = self.nodes[parent].children.last().copied();
+            self.current = parent;
+        }
+    }
+}
+
+pub fn age(time: SystemTime) -> String {
+    let secs = time.elapsed().map_or(0, |d| d.as_secs());
+    match secs {
+        0..60 => format!("{}s ago", secs),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..86400 => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CURSOR: CursorState = CursorState { row: 0, col: 0, selection: None };
+
+    // Appends `text` to the first line as an undo step of its own.
+    fn type_text(history: &mut History, buffer: &mut Buffer, text: &str) {
+        let col = buffer.line_len(0);
+        history.begin_transaction(CURSOR);
+        buffer.insert(0, col, text);
+        history.record(Edit::Insert { row: 0, col, text: text.to_string() }, CURSOR);
+        history.end_transaction();
+    }
+
+    fn at(col: usize) -> CursorState {
+        CursorState { row: 0, col, selection: None }
+    }
+
+    // Types `text` at `col` of the first line as one keystroke of `kind`.
+    fn press(history: &mut History, buffer: &mut Buffer, kind: EditKind, col: usize, text: &str) {
+        history.begin(kind, at(col));
+        buffer.insert(0, col, text);
+        history.record(Edit::Insert { row: 0, col, text: text.to_string() }, at(col + text.len()));
+    }
+
+    fn backspace(history: &mut History, buffer: &mut Buffer) {
+        let col = buffer.line_len(0);
+        history.begin(EditKind::Delete, at(col));
+        let text = buffer.remove(0, col - 1, 0, col);
+        history.record(Edit::Delete { row: 0, col: col - 1, text }, at(col - 1));
+    }
+
+    #[test]
+    fn groups_typing_into_one_step() {
+        let (mut history, mut buffer) = (History::default(), Buffer::new());
+        for (col, c) in ["a", "b", "c"].into_iter().enumerate() {
+            press(&mut history, &mut buffer, EditKind::Insert, col, c);
+        }
+        backspace(&mut history, &mut buffer);
+        backspace(&mut history, &mut buffer);
+        assert_eq!(buffer.text(), "a");
+
+        history.undo(&mut buffer);
+        assert_eq!(buffer.text(), "abc");
+        history.undo(&mut buffer);
+        assert_eq!(buffer.text(), "");
+    }
+
+    #[test]
+    fn starts_a_new_step_after_a_pause() {
+        let (mut history, mut buffer) = (History::default(), Buffer::new());
+        press(&mut history, &mut buffer, EditKind::Insert, 0, "a");
+        history.last_edit = Some(Instant::now() - GROUP_TIMEOUT);
+        press(&mut history, &mut buffer, EditKind::Insert, 1, "b");
+        history.undo(&mut buffer);
+        assert_eq!(buffer.text(), "a");
+    }
+
+    #[test]
+    fn starts_a_new_step_where_the_cursor_moved() {
+        let (mut history, mut buffer) = (History::default(), Buffer::new());
+        press(&mut history, &mut buffer, EditKind::Insert, 0, "a");
+        press(&mut history, &mut buffer, EditKind::Insert, 1, "b");
+        press(&mut history, &mut buffer, EditKind::Insert, 0, "c");
+        assert_eq!(buffer.text(), "cab");
+        history.undo(&mut buffer);
+        assert_eq!(buffer.text(), "ab");
+    }
+
+    #[test]
+    fn gives_new_lines_a_step_of_their_own() {
+        let (mut history, mut buffer) = (History::default(), Buffer::new());
+        press(&mut history, &mut buffer, EditKind::Insert, 0, "a");
+        press(&mut history, &mut buffer, EditKind::Other, 1, "\n");
+        press(&mut history, &mut buffer, EditKind::Insert, 2, "b");
+        assert_eq!(buffer.text(), "a\nb");
+        history.undo(&mut buffer);
+        assert_eq!(buffer.text(), "a\n");
+        history.undo(&mut buffer);
+        assert_eq!(buffer.text(), "a");
+    }
+
+    #[test]
+    fn starts_a_new_step_once_sealed() {
+        let (mut history, mut buffer) = (History::default(), Buffer::new());
+        press(&mut history, &mut buffer, EditKind::Insert, 0, "a");
+        history.seal();
+        press(&mut history, &mut buffer, EditKind::Insert, 1, "b");
+        history.undo(&mut buffer);
+        assert_eq!(buffer.text(), "a");
+    }
+
+    #[test]
+    fn undoes_and_redoes_steps() {
+        let (mut history, mut buffer) = (History::default(), Buffer::new());
+        type_text(&mut history, &mut buffer, "a");
+        type_text(&mut history, &mut buffer, "b");
+
+        assert!(history.undo(&mut buffer).is_some());
+        assert_eq!(buffer.text(), "a");
+        assert!(history.undo(&mut buffer).is_some());
+        assert_eq!(buffer.text(), "");
+        assert!(history.undo(&mut buffer).is_none());
+
+        assert!(history.redo(&mut buffer).is_some());
+        assert!(history.redo(&mut buffer).is_some());
+        assert_eq!(buffer.text(), "ab");
+        assert!(history.redo(&mut buffer).is_none());
+    }
+
+    #[test]
+    fn keeps_undone_steps_on_a_branch() {
+        let (mut history, mut buffer) = (History::default(), Buffer::new());
+        type_text(&mut history, &mut buffer, "a");
+        type_text(&mut history, &mut buffer, "b");
+        history.undo(&mut buffer);
+        type_text(&mut history, &mut buffer, "c");
+        assert_eq!(buffer.text(), "ac");
+
+        history.switch_branch(&mut buffer, false);
+        assert_eq!(buffer.text(), "ab");
+        history.switch_branch(&mut buffer, true);
+        assert_eq!(buffer.text(), "ac");
+        assert!(history.switch_branch(&mut buffer, true).is_none());
+
+        // The newest branch continues the trunk, the older one is drawn off it.
+        let graph: Vec<(usize, String)> = history.layout().into_iter().map(|row| (row.node, row.graph)).collect();
+        assert_eq!(graph, [(0, String::new()), (1, String::new()), (2, "├─ ".to_string()), (3, String::new())]);
+    }
+
+    #[test]
+    fn jumps_across_branches() {
+        let (mut history, mut buffer) = (History::default(), Buffer::new());
+        type_text(&mut history, &mut buffer, "a");
+        type_text(&mut history, &mut buffer, "b");
+        history.undo(&mut buffer);
+        type_text(&mut history, &mut buffer, "c");
+
+        history.jump_to(&mut buffer, 2);
+        assert_eq!(buffer.text(), "ab");
+        history.jump_to(&mut buffer, 0);
+        assert_eq!(buffer.text(), "");
+        history.jump_to(&mut buffer, 3);
+        assert_eq!(buffer.text(), "ac");
+        assert!(history.jump_to(&mut buffer, 3).is_none());
+        assert!(history.jump_to(&mut buffer, 9).is_none());
+    }
+
+    #[test]
+    fn drops_steps_that_were_never_filled() {
+        let (mut history, mut buffer) = (History::default(), Buffer::new());
+        type_text(&mut history, &mut buffer, "a");
+        history.begin(EditKind::Insert, CURSOR);
+        history.undo(&mut buffer);
+        assert_eq!(buffer.text(), "");
+        assert_eq!(history.layout().len(), 2);
+    }
+
+    #[test]
+    fn tells_when_the_saved_state_is_back() {
+        let (mut history, mut buffer) = (History::default(), Buffer::new());
+        type_text(&mut history, &mut buffer, "a");
+        history.mark_saved();
+        type_text(&mut history, &mut buffer, "b");
+        assert!(!history.is_saved());
+        history.undo(&mut buffer);
+        assert!(history.is_saved());
+    }
+
+    #[test]
+    fn undoes_line_ending_changes() {
+        let (mut history, mut buffer) = (History::default(), Buffer::new());
+        history.begin_transaction(CURSOR);
+        buffer.set_line_ending(LineEnding::Crlf);
+        history.record(Edit::LineEnding { from: LineEnding::Lf, to: LineEnding::Crlf }, CURSOR);
+        history.end_transaction();
+        assert_eq!(history.describe(1), "CRLF line endings");
+
+        history.undo(&mut buffer);
+        assert_eq!(buffer.line_ending(), LineEnding::Lf);
+        history.redo(&mut buffer);
+        assert_eq!(buffer.line_ending(), LineEnding::Crlf);
+    }
+}
@@ -1,22 +1,107 @@
-use std::fs;
-use std::io;
+use std::io::{self, BufRead, Read, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 
 mod app;
+mod buffer;
+mod disk;
+mod document;
+mod encoding;
+mod explorer;
+mod finder;
+mod history;
+mod pane;
+mod render_cache;
+mod save;
+mod swap;
+mod terminal;
+mod text;
 mod ui;
+mod undo_cache;
+mod watch;
 
 use std::error::Error;
 use clap::Parser;
 
-use crossterm::{event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers, MouseEvent, MouseEventKind}, execute, terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode}};
-use ratatui::{Terminal, prelude::{Backend, CrosstermBackend}};
-use crate::ui::ui;
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use ratatui::{Terminal, layout::{Position, Rect}, prelude::{Backend, CrosstermBackend}};
+use crate::terminal::TerminalGuard;
+use crate::pane::Orientation;
+use crate::ui::{TabBar, ui};
 
-use crate::app::{App, InputMode, Selection};
+use crate::app::{App, InputMode};
+use crate::document::Selection;
+use crate::finder::Finder;
+use crate::history::EditKind;
 
 #[derive(Parser, Debug)]
 struct Args {
-    filename: String
+    /// Files to open, as `path`, `path:line[:col]` or `+line path`; a directory opens the file finder there and `-` reads standard input
+    #[arg(required = true)]
+    paths: Vec<String>,
+
+    /// Columns to keep visible left and right of the cursor when scrolling horizontally
+    #[arg(long, default_value_t = 4)]
+    side_margin: usize,
+
+    /// Start with soft word-wrap enabled
+    #[arg(long)]
+    wrap: bool,
+
+    /// Watch the files and reload them whenever they change on disk and there are no unsaved changes
+    #[arg(long)]
+    watch: bool,
+
+    /// Open the files in view mode, where they can't be edited
+    #[arg(long)]
+    readonly: bool,
+}
+
+// A file named on the command line and the 1-based line and column to start
+// at in it.
+struct Target {
+    filename: String,
+    line: Option<usize>,
+    column: Option<usize>,
+}
+
+// Sorts out `+line path` pairs and `path:line:col` suffixes. A name that
+// exists as it is, colons and all, is taken as it is.
+fn targets(paths: &[String]) -> Vec<Target> {
+    let mut targets = Vec::new();
+    let mut line = None;
+    for path in paths {
+        if let Some(number) = path.strip_prefix('+').and_then(|n| n.parse().ok()) {
+            line = Some(number);
+            continue;
+        }
+        let (filename, position) = split_position(path);
+        let (line, column) = match (position, line.take()) {
+            (Some((at, column)), _) => (Some(at), column),
+            (None, line) => (line, None),
+        };
+        targets.push(Target { filename: filename.to_string(), line, column });
+    }
+    targets
+}
+
+fn split_position(path: &str) -> (&str, Option<(usize, Option<usize>)>) {
+    if path == "-" || Path::new(path).exists() {
+        return (path, None);
+    }
+    let trimmed = path.strip_suffix(':').unwrap_or(path);
+    let Some((rest, last)) = trimmed.rsplit_once(':').and_then(|(rest, last)| Some((rest, last.parse().ok()?))) else {
+        return (path, None);
+    };
+    // `name:3:5` where a file `name:3` exists is line 5 of that file.
+    if !Path::new(rest).exists()
+        && let Some((name, line)) = rest.rsplit_once(':').and_then(|(name, line)| Some((name, line.parse().ok()?)))
+    {
+        return (name, Some((line, Some(last))));
+    }
+    (rest, Some((last, None)))
 }
 
 
@@ -25,334 +110,447 @@ fn main() -> Result<(), Box<dyn Error>> {
     let args = Args::parse();
 
     let mut app = App::new();
-    app.filename = args.filename.clone();
-    
-    match fs::read_to_string(&args.filename) {
-        Ok(contents) => {
+    app.side_margin = args.side_margin;
+    app.soft_wrap = args.wrap;
+    app.watch = args.watch;
+
+    for target in targets(&args.paths) {
+        let filename = &target.filename;
+        if filename == "-" {
+            let mut bytes = Vec::new();
+            io::stdin().lock().read_to_end(&mut bytes)?;
+            let index = app.open_unnamed(bytes);
+            app.documents[index].read_only |= args.readonly;
+            continue;
+        }
+        if Path::new(filename).is_dir() {
+            app.finder = Some(Finder::new(PathBuf::from(filename)));
+            app.input_buffer.clear();
+            app.input_mode = InputMode::FindFile;
+            continue;
+        }
 
-            app.content = contents.lines().map(String::from).collect();
+        let (index, loaded) = app.open(filename).map_err(|e| format!("Cannot open '{}': {}", filename, e))?;
+        app.documents[index].read_only |= args.readonly;
+        if !loaded {
+            continue;
+        }
 
-            if app.content.is_empty() {
-                app.content.push(String::new());
+        if let Some((swap, written)) = swap::load(filename) {
+            let running = if swap::is_running(swap.pid) {
+                format!(" (Oxide pid {} still seems to be editing it)", swap.pid)
+            } else {
+                String::new()
+            };
+            print!(
+                "Found unsaved changes to '{}' from {}{}.\n[r]ecover, [d]iscard or [k]eep them for later? ",
+                filename,
+                history::age(written),
+                running,
+            );
+            io::stdout().flush()?;
+            let mut answer = String::new();
+            io::stdin().lock().read_line(&mut answer)?;
+            match answer.trim() {
+                "r" | "R" => app.doc_mut().recover(swap),
+                "d" | "D" => swap::remove(filename),
+                _ => {}
             }
         }
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-            app.content.push(String::new());
-            app.modified = true;
+        if let Some(line) = target.line {
+            app.doc_mut().go_to(line, target.column.unwrap_or(1));
         }
-
-        Err(e) => return Err(format!("Cannot open '{}': {}", args.filename, e).into()),
+    }
+    // Only directories were given, so there is just the finder to show.
+    if app.documents.is_empty() {
+        app.open_unnamed(Vec::new());
+    }
+    app.switch_to(0);
+
+    // Save what was being edited before the panic message is printed. The
+    // terminal guard's own hook runs ahead of this one.
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        swap::flush();
+        default_hook(info);
+    }));
+
+    let guard = TerminalGuard::new()?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(io::stderr()))?;
+
+    let result = run_app(&mut terminal, &mut app, &guard);
+    let terminated = guard.terminate_requested();
+    drop(guard);
+
+    // Unsaved changes outlive anything but a normal quit in the swap file.
+    if result.is_err() || terminated {
+        swap::wait();
+        swap::flush();
+        result?;
+        return Ok(());
     }
 
-    enable_raw_mode()?;
-    let mut stderr = io::stderr();
-    execute!(stderr, EnterAlternateScreen, EnableMouseCapture)?;
-    let backend = CrosstermBackend::new(stderr);
-    let mut terminal = Terminal::new(backend)?;
-
-    run_app(&mut terminal, &mut app)?;
-
-    disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen, DisableMouseCapture)?;
-
-    terminal.show_cursor()?;
+    for document in &mut app.documents {
+        document.close();
+    }
+    swap::wait();
 
     Ok(())
 
 }
 
 
-fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App) -> Result<(), Box<dyn Error>>
+// How long the editor has to sit idle before the file is checked for changes
+// made by other programs. In watch mode the check is cheap, only happens when
+// the file was touched, and is done much more often.
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(2);
+const WATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+// How often the finder looks for its file list while that is being made.
+const SCAN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+fn run_app<B: Backend>(terminal: &mut Terminal<B>, app: &mut App, guard: &TerminalGuard) -> Result<(), Box<dyn Error>>
 where
     B::Error: 'static,
 {
     terminal.clear()?;
     
-    loop {
-
-        let viewport_height = terminal.size()?.height.saturating_sub(4) as usize;
-        app.adjust_scroll(viewport_height);
+    while !app.exit && !guard.terminate_requested() {
+
+        let size = terminal.size()?;
+        let areas = ui::pane_areas(app, Rect::new(0, 0, size.width, size.height));
+        let side_margin = app.side_margin;
+        for (pane, area) in areas.iter().enumerate() {
+            app.with_view(pane, |doc| {
+                let width = (area.width as usize).saturating_sub(2 + doc.gutter_width());
+                doc.adjust_scroll(area.height.saturating_sub(2) as usize, width, side_margin);
+            });
+        }
+        let viewport_height = areas[app.focus].height.saturating_sub(2) as usize;
+        let sidebar_height = ui::sidebar_area(app, Rect::new(0, 0, size.width, size.height))
+            .map_or(0, |area| area.height.saturating_sub(2) as usize);
+        if sidebar_height > 0 {
+            app.explorer_mut().adjust_scroll(sidebar_height);
+        }
         
+        if let Some(finder) = &mut app.finder {
+            finder.poll();
+        }
         let _ = terminal.draw(|f| ui(f, app));
+        for document in &mut app.documents {
+            document.update_swap();
+        }
+
+        let watching = app.documents.iter().any(|d| d.watch.is_some());
+        let timeout = if watching { WATCH_INTERVAL } else { DISK_CHECK_INTERVAL };
+        let scanning = app.input_mode == InputMode::FindFile && app.finder.as_ref().is_some_and(Finder::scanning);
+        let timeout = if scanning { SCAN_POLL_INTERVAL } else { timeout };
+        if !event::poll(timeout)? {
+            if app.input_mode == InputMode::Normal {
+                handle_disk_changes(app);
+            }
+            continue;
+        }
 
         match event::read()? {
 
 
             Event::Key(key) => {
+                app.status = None;
                 match app.input_mode {
 
+                    InputMode::Normal if app.doc().read_only && (edits_text(&key) || writes_file(&key)) => {
+                        app.show_error(format!("{} is read-only", app.doc().name()));
+                    }
+
+                    InputMode::Normal if app.doc().binary_warning && edits_text(&key) => {
+                        app.input_mode = InputMode::ConfirmBinaryEdit;
+                    }
+
                     InputMode::Normal => {
                         let shift = key.modifiers.contains(KeyModifiers::SHIFT);
                         
                         match key.code {
 
                             KeyCode::Char('q') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                                if app.modified {
+                                if app.modified_buffers().is_empty() {
+                                    app.exit = true;
+                                } else {
                                     app.input_mode = InputMode::ConfirmQuit;
+                                }
+                            }
+
+                            KeyCode::Char('w') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                close_buffer(app);
+                            }
+
+                            KeyCode::Char('e') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                if app.show_explorer {
+                                    app.show_explorer = false;
                                 } else {
-                                    break;
+                                    app.explorer_mut().refresh();
+                                    app.show_explorer = true;
+                                    app.input_mode = InputMode::Explorer;
                                 }
                             }
 
-                            KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                                let mut content = app.content.join("\n");
-                                content.push('\n');
-                                if fs::write(&app.filename, &content).is_ok() {
-                                    app.modified = false;
+                            KeyCode::Char('b') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                app.input_mode = InputMode::BufferList;
+                                app.input_buffer.clear();
+                                app.buffer_list_index = app.active;
+                            }
+
+                            KeyCode::Char('p') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                match &mut app.finder {
+                                    Some(finder) if finder.root().as_os_str().is_empty() => finder.reopen(),
+                                    finder => *finder = Some(Finder::new(PathBuf::new())),
                                 }
+                                app.input_buffer.clear();
+                                app.input_mode = InputMode::FindFile;
+                            }
+
+                            KeyCode::Char('m') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                let read_only = !app.doc().read_only;
+                                app.doc_mut().read_only = read_only;
+                                let name = app.doc().name();
+                                app.show_message(if read_only { format!("{} is read-only", name) } else { format!("{} can be edited", name) });
+                            }
+
+                            KeyCode::Char('.') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.cycle_buffer(true);
+                            }
+                            KeyCode::Char(',') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.cycle_buffer(false);
+                            }
+                            KeyCode::Char('v') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.split_pane(Orientation::SideBySide);
+                            }
+                            KeyCode::Char('h') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.split_pane(Orientation::Stacked);
+                            }
+                            KeyCode::Char('q') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                if app.panes.len() == 1 {
+                                    app.show_error("Only one pane is open");
+                                } else {
+                                    app.close_pane();
+                                }
+                            }
+                            KeyCode::Char('o') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.cycle_pane(true);
+                            }
+                            KeyCode::Char('O') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.cycle_pane(false);
+                            }
+                            KeyCode::Char('=') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.resize_pane(5);
+                            }
+                            KeyCode::Char('-') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.resize_pane(-5);
+                            }
+
+                            KeyCode::PageDown if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                app.cycle_buffer(true);
+                            }
+                            KeyCode::PageUp if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                app.cycle_buffer(false);
+                            }
+
+                            KeyCode::Char('s') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                save(app);
                             }
 
 
                             KeyCode::Char('z') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                                app.undo();
+                                app.doc_mut().break_undo_group();
+                                guard.suspend()?;
+                                terminal.clear()?;
+                            }
+
+                            KeyCode::Char('z') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.doc_mut().undo();
                             }
 
                             KeyCode::Char('y') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                                app.redo();
+                                app.doc_mut().redo();
+                            }
+
+                            KeyCode::Char('u') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                app.doc_mut().break_undo_group();
+                                let current = app.doc().history.current();
+                                app.undo_tree_index = app.doc().history.layout().iter().position(|r| r.node == current).unwrap_or(0);
+                                app.input_mode = InputMode::UndoTree;
+                            }
+
+                            KeyCode::Char('l') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.doc_mut().convert_line_endings();
+                            }
+
+                            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.input_mode = InputMode::ReopenEncoding;
+                                app.input_buffer.clear();
+                                app.doc_mut().break_undo_group();
+                            }
+
+                            KeyCode::Char('e') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.input_mode = InputMode::SaveEncoding;
+                                app.input_buffer.clear();
+                                app.doc_mut().break_undo_group();
+                            }
+
+                            KeyCode::Char('w') if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.doc_mut().toggle_soft_wrap();
+                            }
+
+                            KeyCode::Left if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.doc_mut().clear_selection();
+                                app.doc_mut().switch_branch(false);
+                            }
+                            KeyCode::Right if key.modifiers.contains(KeyModifiers::ALT) => {
+                                app.doc_mut().clear_selection();
+                                app.doc_mut().switch_branch(true);
                             }
 
                             KeyCode::Char('f') if key.modifiers.contains(KeyModifiers::CONTROL) => {
                                 app.input_mode = InputMode::Search;
                                 app.input_buffer.clear();
+                                app.doc_mut().break_undo_group();
                             }
 
 
                             KeyCode::Char('g') if key.modifiers.contains(KeyModifiers::CONTROL) => {
                                 app.input_mode = InputMode::GoToLine;
                                 app.input_buffer.clear();
+                                app.doc_mut().break_undo_group();
                             }
 
-                            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                                if app.selection.is_some() {
-                                    app.clipboard = app.get_selected_text();
-                                }
+                            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) && app.doc().selection.is_some() => {
+                                app.clipboard = app.doc().get_selected_text();
                             }
-                            KeyCode::Char('x') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                                if app.selection.is_some() {
-                                    app.clipboard = app.get_selected_text();
-                                    app.delete_selection();
-                                }
+                            KeyCode::Char('x') if key.modifiers.contains(KeyModifiers::CONTROL) && app.doc().selection.is_some() => {
+                                app.clipboard = app.doc().get_selected_text();
+                                app.doc_mut().delete_selection();
                             }
                         
                 
-                            KeyCode::Char('v') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                                if !app.clipboard.is_empty() {
-                                    app.save_state();
-                                    if app.selection.is_some() {
-                                        app.delete_selection();
-                                    }
-
-                                    for (i, chunk) in app.clipboard.clone().split('\n').enumerate() {
-                                        if i > 0 {
-                                            let line = app.content.get_mut(app.cursor_row).unwrap();
-                                            let new_line = line.split_off(app.cursor_col);
-
-                                            app.cursor_row += 1;
-                                            app.cursor_col = 0;
-
-                                            app.content.insert(app.cursor_row, new_line);
-                                        }
-                                        if let Some(line) = app.content.get_mut(app.cursor_row) {
-                                            line.insert_str(app.cursor_col, chunk);
-                                            app.cursor_col += chunk.len();
-
-
-                                        }
-                                    }
-                                    app.modified = true;
-
+                            KeyCode::Char('v') if key.modifiers.contains(KeyModifiers::CONTROL) && !app.clipboard.is_empty() => {
+                                app.doc_mut().begin_transaction();
+                                if app.doc().selection.is_some() {
+                                    app.doc_mut().delete_selection();
                                 }
+
+                                let text = app.clipboard.clone();
+                                app.doc_mut().insert_text(&text);
+                                app.doc_mut().end_transaction();
                             }
 
                             KeyCode::Char('a') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                                app.selection = Some(Selection {
+                                app.doc_mut().selection = Some(Selection {
                                     start_row: 0,
                                     start_col: 0,
-                                    end_row: app.content.len().saturating_sub(1),
-                                    end_col: app.content.last().map_or(0, |l| l.len()),
+                                    end_row: app.doc().content.len_lines() - 1,
+                                    end_col: app.doc().line_len(app.doc().content.len_lines() - 1),
                                 });
                             }
 
                             KeyCode::Up => {
-                                if shift && app.selection.is_none() {
-                                    app.start_selection();
+                                if shift && app.doc().selection.is_none() {
+                                    app.doc_mut().start_selection();
                                 } else if !shift {
-                                    app.clear_selection();
-                                }
-                                if app.cursor_row > 0 {
-                                    app.cursor_row -= 1;
-                                    let line_len = app.content.get(app.cursor_row).map_or(0, |l| l.len());
-                                    app.cursor_col = app.cursor_col.min(line_len);
+                                    app.doc_mut().clear_selection();
                                 }
-                                if shift { app.update_selection(); }
+                                app.doc_mut().move_up(1);
+                                if shift { app.doc_mut().update_selection(); }
                             }
                             KeyCode::Down => {
-                                if shift && app.selection.is_none() {
-                                    app.start_selection();
+                                if shift && app.doc().selection.is_none() {
+                                    app.doc_mut().start_selection();
                                 } else if !shift {
-                                    app.clear_selection();
-                                }
-                                if app.cursor_row < app.content.len().saturating_sub(1) {
-                                    app.cursor_row += 1;
-                                    let line_len = app.content.get(app.cursor_row).map_or(0, |l| l.len());
-                                    app.cursor_col = app.cursor_col.min(line_len);
+                                    app.doc_mut().clear_selection();
                                 }
-                                if shift { app.update_selection(); }
+                                app.doc_mut().move_down(1);
+                                if shift { app.doc_mut().update_selection(); }
                             }
 
                             KeyCode::Left => {
-                                if shift && app.selection.is_none() { app.start_selection(); } else if !shift { app.clear_selection(); }
-                                if app.cursor_col > 0 {
-                                    app.cursor_col -= 1;
+                                if shift && app.doc().selection.is_none() { app.doc_mut().start_selection(); } else if !shift { app.doc_mut().clear_selection(); }
+                                app.doc_mut().move_left();
 
-                                } else if app.cursor_row > 0 {
-                                    app.cursor_row -= 1;
-                                    app.cursor_col = app.content.get(app.cursor_row).map_or(0, |l| l.len());
-                                }
-
-                                if shift { app.update_selection(); }
+                                if shift { app.doc_mut().update_selection(); }
                             }
                             KeyCode::Right => {
 
-                                if shift && app.selection.is_none() {
-                                    app.start_selection();
+                                if shift && app.doc().selection.is_none() {
+                                    app.doc_mut().start_selection();
                                 } else if !shift {
-                                    app.clear_selection();
+                                    app.doc_mut().clear_selection();
                                 }
 
-                                let line_len = app.content.get(app.cursor_row).map_or(0, |l| l.len());
-                                if app.cursor_col < line_len {
-                                    app.cursor_col += 1;
-                                } else if app.cursor_row < app.content.len().saturating_sub(1) {
-                                    app.cursor_row += 1;
-                                    app.cursor_col = 0;
-                                }
+                                app.doc_mut().move_right();
 
-                                if shift { app.update_selection(); }
+                                if shift { app.doc_mut().update_selection(); }
                             }
 
                             KeyCode::Home => {
-                                if shift && app.selection.is_none() { app.start_selection(); }
-                                else if !shift { app.clear_selection(); }
-                                app.cursor_col = 0;
+                                if shift && app.doc().selection.is_none() { app.doc_mut().start_selection(); }
+                                else if !shift { app.doc_mut().clear_selection(); }
+                                app.doc_mut().cursor_col = 0;
 
-                                if shift { app.update_selection(); }
+                                if shift { app.doc_mut().update_selection(); }
                             }
                             KeyCode::End => {
-                                if shift && app.selection.is_none() { app.start_selection(); }
-                                else if !shift { app.clear_selection(); }
+                                if shift && app.doc().selection.is_none() { app.doc_mut().start_selection(); }
+                                else if !shift { app.doc_mut().clear_selection(); }
 
-                                app.cursor_col = app.content.get(app.cursor_row).map_or(0, |l| l.len());
-                                if shift { app.update_selection(); }
+                                app.doc_mut().cursor_col = app.doc().line_len(app.doc().cursor_row);
+                                if shift { app.doc_mut().update_selection(); }
                             }
                             KeyCode::PageUp => {
-                                app.clear_selection();
-                                app.cursor_row = app.cursor_row.saturating_sub(viewport_height);
-                                let line_len = app.content.get(app.cursor_row).map_or(0, |l| l.len());
-                                app.cursor_col = app.cursor_col.min(line_len);
+                                app.doc_mut().clear_selection();
+                                app.doc_mut().move_up(viewport_height);
                             }
 
 
                             KeyCode::PageDown => {
-                                app.clear_selection();
-                                app.cursor_row = (app.cursor_row + viewport_height).min(app.content.len().saturating_sub(1));
-                                let line_len = app.content.get(app.cursor_row).map_or(0, |l| l.len());
-                                app.cursor_col = app.cursor_col.min(line_len);
+                                app.doc_mut().clear_selection();
+                                app.doc_mut().move_down(viewport_height);
                             }
                             KeyCode::Tab => {
-
-                                app.save_state();
-                                if app.selection.is_some() {
-                                    app.delete_selection();
-
-                                }
-                                if let Some(line) = app.content.get_mut(app.cursor_row) {
-                                    line.insert_str(app.cursor_col, "    ");
-                                    app.cursor_col += 4;
-                                    app.modified = true;
-
-                                }
+                                app.doc_mut().begin_replacing_selection(EditKind::Insert);
+                                app.doc_mut().insert_text("    ");
 
                             }
                             KeyCode::Delete => {
-                                if app.selection.is_some() { app.delete_selection();
+                                if app.doc().selection.is_some() { app.doc_mut().delete_selection();
                                 } else {
-                                    let line_len = app.content.get(app.cursor_row).map_or(0, |l| l.len());
-                                    if app.cursor_col < line_len {
-                                        
-
-                                        app.save_state();
-                                        if let Some(line) = app.content.get_mut(app.cursor_row) {
-                                            line.remove(app.cursor_col);
-                                        
-                                        }
-                                        app.modified = true;
-                                    } else if app.cursor_row < app.content.len().saturating_sub(1) {
-                                        app.save_state();
-                                        let next_line = app.content.remove(app.cursor_row + 1);
-                                        app.content[app.cursor_row].push_str(&next_line);
-                                        app.modified = true;
-                                    }
+                                    app.doc_mut().begin_edit(EditKind::Delete);
+                                    app.doc_mut().delete_forward();
 
                                 }
 
                             }
+                            KeyCode::Char(_) if key.modifiers.contains(KeyModifiers::CONTROL) => {}
+
                             KeyCode::Char(c) => {
-                                app.save_state();
-                                if app.selection.is_some() {
-                                    app.delete_selection();
-                                }
-                                if let Some(line) = app.content.get_mut(app.cursor_row) {
-                                    line.insert(app.cursor_col, c);
-                                    app.cursor_col += 1;
-                                    app.modified = true;
-                                }
+                                app.doc_mut().begin_replacing_selection(EditKind::Insert);
+                                app.doc_mut().insert_text(c.encode_utf8(&mut [0; 4]));
                             }
 
                             KeyCode::Backspace => {
-                                if app.selection.is_some() {
-                                    app.delete_selection();
+                                if app.doc().selection.is_some() {
+                                    app.doc_mut().delete_selection();
 
                                 } else {
-                                    app.save_state();
-                                    if app.cursor_col > 0 {
-                                        if let Some(line) = app.content.get_mut(app.cursor_row) {
-                                            line.remove(app.cursor_col - 1);
-                                            app.cursor_col -= 1;
-
-
-                                        }
-                                    } else if app.cursor_row > 0 {
-                                        let current_line = app.content.remove(app.cursor_row);
-                                        app.cursor_row -= 1;
-
-                                        app.cursor_col = app.content[app.cursor_row].len();
-                                        app.content[app.cursor_row].push_str(&current_line);
-                                    }
-                                    app.modified = true;
+                                    app.doc_mut().begin_edit(EditKind::Delete);
+                                    app.doc_mut().backspace();
                                 }
                             }
 
                             KeyCode::Enter => {
-                                app.save_state();
-                                if app.selection.is_some() {
-                                    app.delete_selection();
-                                }
-                                if let Some(line) = app.content.get_mut(app.cursor_row) {
-                                    let new_line = line.split_off(app.cursor_col);
-
-                                    app.cursor_row += 1;
-                                    app.cursor_col = 0;
-
-                                    app.content.insert(app.cursor_row, new_line);
-
-                                    app.modified = true;
-
-
-                                }
+                                app.doc_mut().begin_replacing_selection(EditKind::Other);
+                                app.doc_mut().insert_text("\n");
                             }
                             KeyCode::Esc => {
-                                app.clear_selection();
+                                app.doc_mut().clear_selection();
                             }
 
 
@@ -365,18 +563,18 @@ where
 
                             KeyCode::Esc => {
                                 app.input_mode = InputMode::Normal;
-                                app.search_matches.clear();
+                                app.doc_mut().search_matches.clear();
                             }
 
                             KeyCode::Enter => {
 
-                                app.search_query = app.input_buffer.clone();
-                                app.search();
+                                app.doc_mut().search_query = app.input_buffer.clone();
+                                app.doc_mut().search();
 
-                                if !app.search_matches.is_empty() {
-                                    let (row, col) = app.search_matches[0];
-                                    app.cursor_row = row;
-                                    app.cursor_col = col;
+                                if !app.doc().search_matches.is_empty() {
+                                    let (row, col) = app.doc().search_matches[0];
+                                    app.doc_mut().cursor_row = row;
+                                    app.doc_mut().cursor_col = col;
 
                                 }
                                 app.input_mode = InputMode::Normal;
@@ -390,17 +588,17 @@ where
                                 app.input_buffer.pop();
                             }
                             KeyCode::Down | KeyCode::Tab => {
-                                app.search_query = app.input_buffer.clone();
-                                app.search();
-                                app.next_match();
+                                app.doc_mut().search_query = app.input_buffer.clone();
+                                app.doc_mut().search();
+                                app.doc_mut().next_match();
                             
                             }
                             
                             
                             KeyCode::Up => {
-                                app.search_query = app.input_buffer.clone();
-                                app.search();
-                                app.prev_match();
+                                app.doc_mut().search_query = app.input_buffer.clone();
+                                app.doc_mut().search();
+                                app.doc_mut().prev_match();
                             }
                             
                             _ => {}
@@ -417,10 +615,10 @@ where
 
                             KeyCode::Enter => {
                                 if let Ok(line_num) = app.input_buffer.parse::<usize>() {
-                                    let target = line_num.saturating_sub(1).min(app.content.len().saturating_sub(1));
+                                    let target = line_num.saturating_sub(1).min(app.doc().content.len_lines() - 1);
                                     
-                                    app.cursor_row = target;
-                                    app.cursor_col = 0;
+                                    app.doc_mut().cursor_row = target;
+                                    app.doc_mut().cursor_col = 0;
                                     
                                 }
                                 app.input_mode = InputMode::Normal;
@@ -437,11 +635,328 @@ where
 
                         }
                     }
+                    InputMode::UndoTree => {
+                        match key.code {
+                            KeyCode::Esc => {
+                                app.input_mode = InputMode::Normal;
+                            }
+                            KeyCode::Up => {
+                                app.undo_tree_index = app.undo_tree_index.saturating_sub(1);
+                            }
+                            KeyCode::Down => {
+                                let rows = app.doc().history.layout().len();
+                                app.undo_tree_index = (app.undo_tree_index + 1).min(rows - 1);
+                            }
+                            KeyCode::Enter => {
+                                if let Some(row) = app.doc().history.layout().get(app.undo_tree_index) {
+                                    app.doc_mut().jump_to_history(row.node);
+                                }
+                                app.input_mode = InputMode::Normal;
+                            }
+                            _ => {}
+                        }
+                    }
+                    InputMode::ReopenEncoding | InputMode::SaveEncoding => {
+                        match key.code {
+                            KeyCode::Esc => {
+                                app.input_mode = InputMode::Normal;
+                            }
+
+                            // Unknown labels leave the prompt open for another try.
+                            KeyCode::Enter => {
+                                if let Some((encoding, bom)) = encoding::for_label(&app.input_buffer) {
+                                    let reopen = app.input_mode == InputMode::ReopenEncoding;
+                                    app.input_mode = InputMode::Normal;
+                                    if reopen {
+                                        match app.doc_mut().reopen_with_encoding(encoding) {
+                                            Ok(()) => app.show_message(format!("Reopened as {}", encoding.name())),
+                                            Err(e) => app.show_error(format!("Cannot reopen '{}': {}", app.doc().label(), e)),
+                                        }
+                                    } else {
+                                        app.pending_encoding = Some((encoding, bom));
+                                        save(app);
+                                    }
+                                }
+                            }
+                            KeyCode::Char(c) => {
+                                app.input_buffer.push(c);
+                            }
+                            KeyCode::Backspace => {
+                                app.input_buffer.pop();
+                            }
+                            _ => {}
+                        }
+                    }
+                    InputMode::SaveAs => {
+                        match key.code {
+                            KeyCode::Esc => {
+                                app.pending_encoding = None;
+                                app.input_mode = InputMode::Normal;
+                            }
+                            KeyCode::Enter if !app.input_buffer.is_empty() => {
+                                let filename = app.input_buffer.clone();
+                                if Path::new(&filename).exists() {
+                                    app.show_error(format!("'{}' already exists", filename));
+                                } else {
+                                    app.input_mode = InputMode::Normal;
+                                    app.doc_mut().filename = filename;
+                                    if write(app) != Saved::Written {
+                                        app.doc_mut().filename.clear();
+                                    }
+                                }
+                            }
+                            KeyCode::Char(c) => {
+                                app.input_buffer.push(c);
+                            }
+                            KeyCode::Backspace => {
+                                app.input_buffer.pop();
+                            }
+                            _ => {}
+                        }
+                    }
+                    InputMode::ConfirmBinaryEdit => {
+                        match key.code {
+                            KeyCode::Char('y') | KeyCode::Char('Y') => {
+                                app.doc_mut().binary_warning = false;
+                                app.input_mode = InputMode::Normal;
+                            }
+                            KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                                app.input_mode = InputMode::Normal;
+                            }
+                            _ => {}
+                        }
+                    }
+                    InputMode::ExternalChange => {
+                        match key.code {
+                            KeyCode::Char('r') | KeyCode::Char('R') => {
+                                app.pending_encoding = None;
+                                app.input_mode = InputMode::Normal;
+                                match app.doc_mut().reload() {
+                                    Ok(()) => app.show_message(format!("Reloaded {}", app.doc().filename)),
+                                    Err(e) => app.show_error(format!("Cannot reload '{}': {}", app.doc().filename, e)),
+                                }
+                            }
+                            KeyCode::Char('o') | KeyCode::Char('O') => {
+                                app.input_mode = InputMode::Normal;
+                                write(app);
+                            }
+                            KeyCode::Char('d') | KeyCode::Char('D') => {
+                                match app.doc().disk_diff() {
+                                    Ok(diff) => {
+                                        app.disk_diff = diff;
+                                        app.disk_diff_scroll = 0;
+                                        app.input_mode = InputMode::DiskDiff;
+                                    }
+                                    Err(e) => app.show_error(format!("Cannot read '{}': {}", app.doc().filename, e)),
+                                }
+                            }
+                            KeyCode::Esc => {
+                                app.pending_encoding = None;
+                                app.input_mode = InputMode::Normal;
+                            }
+                            _ => {}
+                        }
+                    }
+                    InputMode::DiskDiff => {
+                        let page = viewport_height.saturating_sub(2).max(1);
+                        let last = app.disk_diff.len().saturating_sub(1);
+                        match key.code {
+                            KeyCode::Up => {
+                                app.disk_diff_scroll = app.disk_diff_scroll.saturating_sub(1);
+                            }
+                            KeyCode::Down => {
+                                app.disk_diff_scroll = (app.disk_diff_scroll + 1).min(last);
+                            }
+                            KeyCode::PageUp => {
+                                app.disk_diff_scroll = app.disk_diff_scroll.saturating_sub(page);
+                            }
+                            KeyCode::PageDown => {
+                                app.disk_diff_scroll = (app.disk_diff_scroll + page).min(last);
+                            }
+                            KeyCode::Esc | KeyCode::Char('q') => {
+                                app.input_mode = InputMode::ExternalChange;
+                            }
+                            _ => {}
+                        }
+                    }
+                    InputMode::BufferList => {
+                        let matches = app.buffer_matches();
+                        match key.code {
+                            KeyCode::Esc => {
+                                app.input_mode = InputMode::Normal;
+                            }
+                            KeyCode::Enter => {
+                                if let Some(&index) = matches.get(app.buffer_list_index) {
+                                    app.switch_to(index);
+                                }
+                                app.input_mode = InputMode::Normal;
+                            }
+                            KeyCode::Up => {
+                                app.buffer_list_index = app.buffer_list_index.saturating_sub(1);
+                            }
+                            KeyCode::Down => {
+                                app.buffer_list_index = (app.buffer_list_index + 1).min(matches.len().saturating_sub(1));
+                            }
+                            KeyCode::Char(c) => {
+                                app.input_buffer.push(c);
+                                app.buffer_list_index = 0;
+                            }
+                            KeyCode::Backspace => {
+                                app.input_buffer.pop();
+                                app.buffer_list_index = 0;
+                            }
+                            _ => {}
+                        }
+                    }
+                    InputMode::FindFile => {
+                        let Some(finder) = app.finder.as_mut() else {
+                            app.input_mode = InputMode::Normal;
+                            continue;
+                        };
+                        let page = viewport_height.max(1) as isize;
+                        match key.code {
+                            KeyCode::Esc => {
+                                app.input_mode = InputMode::Normal;
+                            }
+                            KeyCode::Enter => {
+                                let path = finder.selected_path();
+                                app.input_mode = InputMode::Normal;
+                                if let Some(path) = path {
+                                    open_file(app, &path.display().to_string());
+                                }
+                            }
+                            KeyCode::Up => finder.move_selection(-1),
+                            KeyCode::Down => finder.move_selection(1),
+                            KeyCode::PageUp => finder.move_selection(-page),
+                            KeyCode::PageDown => finder.move_selection(page),
+                            KeyCode::Char(c) => {
+                                app.input_buffer.push(c);
+                                finder.update(&app.input_buffer);
+                            }
+                            KeyCode::Backspace => {
+                                app.input_buffer.pop();
+                                finder.update(&app.input_buffer);
+                            }
+                            _ => {}
+                        }
+                    }
+                    InputMode::Explorer => {
+                        let page = sidebar_height.max(1) as isize;
+                        match key.code {
+                            KeyCode::Esc => {
+                                app.input_mode = InputMode::Normal;
+                            }
+                            KeyCode::Char('e') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                                app.show_explorer = false;
+                                app.input_mode = InputMode::Normal;
+                            }
+                            KeyCode::Up => app.explorer_mut().move_selection(-1),
+                            KeyCode::Down => app.explorer_mut().move_selection(1),
+                            KeyCode::PageUp => app.explorer_mut().move_selection(-page),
+                            KeyCode::PageDown => app.explorer_mut().move_selection(page),
+                            KeyCode::Home => app.explorer_mut().selected = 0,
+                            KeyCode::End => app.explorer_mut().move_selection(isize::MAX),
+                            KeyCode::Right => app.explorer_mut().set_expanded(true),
+                            KeyCode::Left => {
+                                let explorer = app.explorer_mut();
+                                if explorer.selected().is_some_and(|entry| entry.expanded) {
+                                    explorer.set_expanded(false);
+                                } else {
+                                    explorer.select_parent();
+                                }
+                            }
+                            KeyCode::Enter => {
+                                activate_entry(app);
+                            }
+                            KeyCode::Char('n') => {
+                                app.input_buffer.clear();
+                                app.input_mode = InputMode::ExplorerCreate;
+                            }
+                            KeyCode::Char('r') => {
+                                if let Some(entry) = app.explorer_mut().selected() {
+                                    app.input_buffer = entry.name.clone();
+                                    app.input_mode = InputMode::ExplorerRename;
+                                }
+                            }
+                            KeyCode::Char('d') | KeyCode::Delete if app.explorer_mut().selected().is_some() => {
+                                app.input_mode = InputMode::ConfirmDelete;
+                            }
+                            _ => {}
+                        }
+                    }
+                    InputMode::ExplorerCreate | InputMode::ExplorerRename => {
+                        match key.code {
+                            KeyCode::Esc => {
+                                app.input_mode = InputMode::Explorer;
+                            }
+                            KeyCode::Enter if !app.input_buffer.is_empty() => {
+                                let name = app.input_buffer.clone();
+                                let create = app.input_mode == InputMode::ExplorerCreate;
+                                app.input_mode = InputMode::Explorer;
+                                if create {
+                                    match app.explorer_mut().create(&name) {
+                                        Ok(path) if !name.ends_with('/') => {
+                                            let filename = app.explorer_mut().relative(&path);
+                                            open_file(app, &filename);
+                                            app.input_mode = InputMode::Normal;
+                                        }
+                                        Ok(_) => {}
+                                        Err(e) => app.show_error(format!("Cannot create '{}': {}", name, e)),
+                                    }
+                                } else {
+                                    match app.explorer_mut().rename(&name) {
+                                        Ok((from, to)) => {
+                                            app.renamed(&from, &to);
+                                            app.show_message(format!("Renamed to {}", name));
+                                        }
+                                        Err(e) => app.show_error(format!("Cannot rename to '{}': {}", name, e)),
+                                    }
+                                }
+                            }
+                            KeyCode::Char(c) => {
+                                app.input_buffer.push(c);
+                            }
+                            KeyCode::Backspace => {
+                                app.input_buffer.pop();
+                            }
+                            _ => {}
+                        }
+                    }
+                    InputMode::ConfirmDelete => {
+                        match key.code {
+                            KeyCode::Char('y') | KeyCode::Char('Y') => {
+                                app.input_mode = InputMode::Explorer;
+                                match app.explorer_mut().delete() {
+                                    Ok(path) => {
+                                        let name = app.explorer_mut().relative(&path);
+                                        app.show_message(format!("Deleted {}", name));
+                                    }
+                                    Err(e) => app.show_error(format!("Cannot delete: {}", e)),
+                                }
+                            }
+                            KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                                app.input_mode = InputMode::Explorer;
+                            }
+                            _ => {}
+                        }
+                    }
+                    InputMode::ConfirmClose => {
+                        match key.code {
+                            KeyCode::Char('y') | KeyCode::Char('Y') => {
+                                app.input_mode = InputMode::Normal;
+                                app.close_buffer();
+                            }
+                            KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
+                                app.input_mode = InputMode::Normal;
+                            }
+                            _ => {}
+                        }
+                    }
                     InputMode::ConfirmQuit => {
                         match key.code {
 
                             KeyCode::Char('y') | KeyCode::Char('Y') => {
-                                break;
+                                app.exit = true;
                             }
 
                             KeyCode::Char('n') | KeyCode::Char('N') | KeyCode::Esc => {
@@ -453,23 +968,49 @@ where
                     }
                 }
             }
-            Event::Mouse(MouseEvent { kind: MouseEventKind::Down(_), column, row, .. }) => {
-                if app.input_mode == InputMode::Normal {
-                    let editor_start_row = 1u16;
-                    let editor_start_col = (app.content.len().to_string().len() + 5) as u16;
-                    
-                    if row > editor_start_row && column >= editor_start_col {
-                        let clicked_row = (row - editor_start_row - 1) as usize + app.scroll_offset;
+            Event::Mouse(MouseEvent { kind: MouseEventKind::Down(button), column, row: 0, .. }) if matches!(app.input_mode, InputMode::Normal | InputMode::Explorer) => {
+                app.input_mode = InputMode::Normal;
+                let tab_bar = TabBar::new(app, size.width);
+                if button == MouseButton::Middle {
+                    if let Some(index) = tab_bar.tab_at(column) {
+                        app.switch_to(index);
+                        close_buffer(app);
+                    }
+                } else if let Some(index) = tab_bar.buffer_at(column, size.width) {
+                    app.switch_to(index);
+                }
+            }
+            Event::Mouse(MouseEvent { kind: MouseEventKind::Down(_), column, row, .. }) if matches!(app.input_mode, InputMode::Normal | InputMode::Explorer) => {
+                let position = Position::new(column, row);
+                if let Some(sidebar) = ui::sidebar_area(app, Rect::new(0, 0, size.width, size.height))
+                    && sidebar.contains(position)
+                {
+                    if row > sidebar.y && row + 1 < sidebar.bottom() {
+                        let explorer = app.explorer_mut();
+                        let index = explorer.scroll + (row - sidebar.y - 1) as usize;
+                        if index < explorer.entries.len() {
+                            explorer.selected = index;
+                            app.input_mode = InputMode::Explorer;
+                            activate_entry(app);
+                        }
+                    }
+                } else if let Some(pane) = areas.iter().position(|area| area.contains(position)) {
+                    app.focus_pane(pane);
+                    let area = areas[pane];
+                    let editor_start_row = area.y + 1;
+                    let editor_start_col = area.x + 1 + app.doc().gutter_width() as u16;
+
+                    if row >= editor_start_row && row + 1 < area.bottom() && column >= editor_start_col && column + 1 < area.right() {
+                        let clicked_row = (row - editor_start_row) as usize;
                         let clicked_col = (column - editor_start_col) as usize;
-                        
-                        if clicked_row < app.content.len() {
-                            app.cursor_row = clicked_row;
-                            app.cursor_col = clicked_col.min(app.content[clicked_row].len());
-                            app.clear_selection();
+
+                        if let Some((row, col)) = app.doc().position_at(clicked_row, clicked_col) {
+                            app.doc_mut().cursor_row = row;
+                            app.doc_mut().cursor_col = col;
+                            app.doc_mut().clear_selection();
 
                         }
                     }
-                    
                 }
             }
 
@@ -481,4 +1022,191 @@ where
 
     Ok(())
 
-}
\ No newline at end of file
+}
+
+// In watch mode a clean buffer simply follows its file. Otherwise, and when
+// there are unsaved changes, the user decides. That is only asked about the
+// buffer on screen; the others are checked once they are switched to.
+fn handle_disk_changes(app: &mut App) {
+    for index in 0..app.documents.len() {
+        let document = &mut app.documents[index];
+        let follows = document.watch.is_some() && !document.modified;
+        if index != app.active && !follows {
+            continue;
+        }
+        let touched = document.watch.as_mut().is_none_or(|watch| watch.changed());
+        if !touched || !document.new_disk_change() {
+            continue;
+        }
+        if !follows {
+            app.input_mode = InputMode::ExternalChange;
+            continue;
+        }
+        let filename = document.filename.clone();
+        match document.reload() {
+            Ok(()) => app.show_message(format!("Reloaded {}", filename)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => app.show_error(format!("'{}' was deleted", filename)),
+            Err(e) => app.show_error(format!("Cannot reload '{}': {}", filename, e)),
+        }
+    }
+}
+
+// Opens a file from inside the editor. There is no asking about unsaved
+// changes left in a swap file before the file is shown, so they are brought
+// back straight away, as a step that can be undone.
+fn open_file(app: &mut App, filename: &str) {
+    match app.open(filename) {
+        Ok((_, true)) => {}
+        Ok((_, false)) => return,
+        Err(e) => {
+            app.show_error(format!("Cannot open '{}': {}", filename, e));
+            return;
+        }
+    }
+    if let Some((swap, written)) = swap::load(filename) {
+        if swap::is_running(swap.pid) {
+            app.show_error(format!("Oxide pid {} still seems to be editing '{}'", swap.pid, filename));
+        } else {
+            app.doc_mut().recover(swap);
+            app.show_message(format!("Recovered unsaved changes from {}", history::age(written)));
+        }
+    }
+}
+
+// Opens the file selected in the file tree, or expands or collapses the
+// selected directory.
+fn activate_entry(app: &mut App) {
+    let explorer = app.explorer_mut();
+    let Some(entry) = explorer.selected() else { return };
+    if entry.is_dir {
+        explorer.toggle();
+        return;
+    }
+    let filename = explorer.relative(&entry.path);
+    open_file(app, &filename);
+    app.input_mode = InputMode::Normal;
+}
+
+// Closes the active buffer, asking first if it has unsaved changes.
+fn close_buffer(app: &mut App) {
+    if app.doc().modified {
+        app.input_mode = InputMode::ConfirmClose;
+    } else {
+        app.close_buffer();
+    }
+}
+
+// How a save went. A deferred save waits on a prompt, which saves or not
+// once it is answered.
+#[derive(PartialEq)]
+enum Saved {
+    Written,
+    Deferred,
+    Failed,
+}
+
+// Saves unless another program changed the file since it was read, in which
+// case the user is asked what to do first.
+fn save(app: &mut App) -> Saved {
+    if app.doc_mut().disk_changed() {
+        app.input_mode = InputMode::ExternalChange;
+        return Saved::Deferred;
+    }
+    write(app)
+}
+
+// Saves the active buffer as it is, or in the pending encoding if there is
+// one, and reports how that went. A buffer without a file asks for a name
+// first. The encoding only changes if the file was written in it.
+fn write(app: &mut App) -> Saved {
+    if app.doc().read_only {
+        app.pending_encoding = None;
+        app.show_error(format!("{} is read-only", app.doc().name()));
+        return Saved::Failed;
+    }
+    if app.doc().filename.is_empty() {
+        app.input_buffer.clear();
+        app.input_mode = InputMode::SaveAs;
+        return Saved::Deferred;
+    }
+    let previous = (app.doc().encoding, app.doc().bom);
+    if let Some((encoding, bom)) = app.pending_encoding.take() {
+        (app.doc_mut().encoding, app.doc_mut().bom) = (encoding, bom);
+    }
+    match app.doc_mut().save() {
+        Ok(len) => {
+            app.show_message(format!("Wrote {} ({} bytes)", app.doc().filename, len));
+            Saved::Written
+        }
+        Err(e) => {
+            (app.doc_mut().encoding, app.doc_mut().bom) = previous;
+            app.show_error(format!("Cannot save '{}': {}", app.doc().filename, e));
+            Saved::Failed
+        }
+    }
+}
+
+// Whether `key` writes the file when pressed in normal mode.
+fn writes_file(key: &KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char('s') => key.modifiers.contains(KeyModifiers::CONTROL),
+        KeyCode::Char('e') => key.modifiers.contains(KeyModifiers::ALT),
+        _ => false,
+    }
+}
+
+// Whether `key` changes the text when pressed in normal mode.
+fn edits_text(key: &KeyEvent) -> bool {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    let alt = key.modifiers.contains(KeyModifiers::ALT);
+    match key.code {
+        KeyCode::Char('y' | 'u' | 'x' | 'v') if ctrl => true,
+        KeyCode::Char('z' | 'l') if alt => true,
+        KeyCode::Char('r' | 'e' | 'w' | 'm' | '.' | ',' | 'v' | 'h' | 'q' | 'o' | 'O' | '=' | '-') if alt => false,
+        KeyCode::Left | KeyCode::Right if alt => true,
+        KeyCode::Char(_) => !ctrl,
+        KeyCode::Tab | KeyCode::Enter | KeyCode::Backspace | KeyCode::Delete => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::*;
+
+    fn parsed(paths: &[&str]) -> Vec<(String, Option<usize>, Option<usize>)> {
+        let paths: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+        targets(&paths).into_iter().map(|t| (t.filename, t.line, t.column)).collect()
+    }
+
+    #[test]
+    fn splits_line_and_column_off_names() {
+        assert_eq!(split_position("a:3:5"), ("a", Some((3, Some(5)))));
+        assert_eq!(split_position("a:3"), ("a", Some((3, None))));
+        assert_eq!(split_position("a:3:"), ("a", Some((3, None))));
+        assert_eq!(split_position("a:b"), ("a:b", None));
+        assert_eq!(split_position("a:b:3"), ("a:b", Some((3, None))));
+        assert_eq!(split_position("-"), ("-", None));
+    }
+
+    #[test]
+    fn takes_plus_line_for_the_next_file() {
+        assert_eq!(
+            parsed(&["+3", "a", "b", "c:7:2"]),
+            [("a".to_string(), Some(3), None), ("b".to_string(), None, None), ("c".to_string(), Some(7), Some(2))],
+        );
+        assert_eq!(parsed(&["+x"]), [("+x".to_string(), None, None)]);
+    }
+
+    #[test]
+    fn keeps_existing_names_with_colons_whole() {
+        let path = env::temp_dir().join(format!("oxide-{}-a:3", process::id()));
+        fs::write(&path, "").unwrap();
+        let name = path.display().to_string();
+        let parsed = parsed(&[&name, &format!("{}:5", name), &format!("{}:5:2", name)]);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(parsed, [(name.clone(), None, None), (name.clone(), Some(5), None), (name, Some(5), Some(2))]);
+    }
+}
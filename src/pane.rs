@@ -0,0 +1,121 @@
+use std::cell::RefCell;
+
+use crate::document::View;
+use crate::render_cache::RenderCache;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Orientation {
+    SideBySide,
+    Stacked,
+}
+
+// A part of the editor area showing one of the documents. The view is only
+// kept up to date while the pane is not focused; the focused pane's view is
+// the one held by its document.
+pub struct Pane {
+    pub document: usize,
+    pub view: View,
+    pub render_cache: RefCell<RenderCache>,
+}
+
+impl Pane {
+    pub fn new(document: usize, view: View) -> Pane {
+        Pane { document, view, render_cache: RefCell::new(RenderCache::default()) }
+    }
+}
+
+// How the editor area is divided between the panes, by index. Each split
+// gives `percent` of its space to `first`.
+pub enum PaneLayout {
+    Pane(usize),
+    Split { orientation: Orientation, percent: u16, first: Box<PaneLayout>, second: Box<PaneLayout> },
+}
+
+impl PaneLayout {
+    // The panes from left to right and top to bottom.
+    pub fn panes(&self) -> Vec<usize> {
+        match self {
+            PaneLayout::Pane(pane) => vec![*pane],
+            PaneLayout::Split { first, second, .. } => {
+                let mut panes = first.panes();
+                panes.extend(second.panes());
+                panes
+            }
+        }
+    }
+
+    fn contains(&self, pane: usize) -> bool {
+        match self {
+            PaneLayout::Pane(p) => *p == pane,
+            PaneLayout::Split { first, second, .. } => first.contains(pane) || second.contains(pane),
+        }
+    }
+
+    // Halves the space of `pane`, putting `new` after it.
+    pub fn split(&mut self, pane: usize, new: usize, orientation: Orientation) {
+        match self {
+            PaneLayout::Pane(p) if *p == pane => {
+                *self = PaneLayout::Split {
+                    orientation,
+                    percent: 50,
+                    first: Box::new(PaneLayout::Pane(pane)),
+                    second: Box::new(PaneLayout::Pane(new)),
+                };
+            }
+            PaneLayout::Pane(_) => {}
+            PaneLayout::Split { first, second, .. } => {
+                first.split(pane, new, orientation);
+                second.split(pane, new, orientation);
+            }
+        }
+    }
+
+    // Takes `pane` out, giving its space to whatever shared the split with
+    // it. Panes after it move down an index to match.
+    pub fn remove(&mut self, pane: usize) {
+        self.take_out(pane);
+        self.renumber(pane);
+    }
+
+    fn take_out(&mut self, pane: usize) {
+        if let PaneLayout::Split { first, second, .. } = self {
+            if matches!(**first, PaneLayout::Pane(p) if p == pane) {
+                *self = *std::mem::replace(second, Box::new(PaneLayout::Pane(0)));
+            } else if matches!(**second, PaneLayout::Pane(p) if p == pane) {
+                *self = *std::mem::replace(first, Box::new(PaneLayout::Pane(0)));
+            } else {
+                first.take_out(pane);
+                second.take_out(pane);
+            }
+        }
+    }
+
+    fn renumber(&mut self, removed: usize) {
+        match self {
+            PaneLayout::Pane(p) if *p > removed => *p -= 1,
+            PaneLayout::Pane(_) => {}
+            PaneLayout::Split { first, second, .. } => {
+                first.renumber(removed);
+                second.renumber(removed);
+            }
+        }
+    }
+
+    // Grows `pane` by `delta` percent of the split it was made by, or shrinks
+    // it for a negative `delta`.
+    pub fn resize(&mut self, pane: usize, delta: i16) {
+        let PaneLayout::Split { percent, first, second, .. } = self else { return };
+        let (child, sign) = if first.contains(pane) {
+            (first, 1)
+        } else if second.contains(pane) {
+            (second, -1)
+        } else {
+            return;
+        };
+        if matches!(**child, PaneLayout::Pane(_)) {
+            *percent = (*percent as i16 + sign * delta).clamp(10, 90) as u16;
+        } else {
+            child.resize(pane, delta);
+        }
+    }
+}
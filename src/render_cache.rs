@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::mem;
+
+use ratatui::text::Span;
+
+// Remembers how lines were drawn so that redrawing a viewport whose lines
+// haven't changed doesn't redo grapheme segmentation for each of them.
+// Entries are keyed by the text of the line and the columns shown, so an
+// edit only costs the lines it touched. Each pane has its own, holding what
+// it drew in its last frame.
+#[derive(Default)]
+pub struct RenderCache {
+    previous: HashMap<(u64, usize, usize), Span<'static>>,
+    current: HashMap<(u64, usize, usize), Span<'static>>,
+}
+
+impl RenderCache {
+    // The drawn form of `line` from display column `skip` on, `width`
+    // columns wide.
+    pub fn span(&mut self, line: &str, skip: usize, width: usize, build: impl FnOnce() -> Span<'static>) -> Span<'static> {
+        let key = (hash(line), skip, width);
+        if let Some(span) = self.current.get(&key) {
+            return span.clone();
+        }
+        let span = self.previous.remove(&key).unwrap_or_else(build);
+        self.current.insert(key, span.clone());
+        span
+    }
+
+    // Forgets whatever wasn't drawn since the last call.
+    pub fn end_frame(&mut self) {
+        self.previous = mem::take(&mut self.current);
+    }
+}
+
+// How many lines `WrapCache` holds before it starts over.
+const WRAP_CACHE_SIZE: usize = 4096;
+
+// Where lines break when soft-wrapped, by the text of the line and the
+// width, so panes of different widths on the same document don't push each
+// other's entries out.
+#[derive(Default)]
+pub struct WrapCache {
+    points: HashMap<(u64, usize), Vec<usize>>,
+}
+
+impl WrapCache {
+    pub fn wrap_points(&mut self, line: &str, width: usize, build: impl FnOnce() -> Vec<usize>) -> Vec<usize> {
+        if self.points.len() >= WRAP_CACHE_SIZE {
+            self.points.clear();
+        }
+        self.points.entry((hash(line), width)).or_insert_with(build).clone()
+    }
+}
+
+fn hash(line: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    line.hash(&mut hasher);
+    hasher.finish()
+}
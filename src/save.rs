@@ -0,0 +1,108 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt, fchown};
+use std::path::{Path, PathBuf};
+use std::process;
+
+// Writes `bytes` to `path` so that a crash or a full disk leaves either the old
+// contents or the new ones, never a mix. The data goes to a temporary file next
+// to the target, is flushed to disk and then renamed over it. A symlink is
+// followed and its target replaced, and the original permissions and owner
+// are carried over to the new file.
+//
+// When renaming would lose something that can't be carried over (other hard
+// links to the file, an owner we aren't allowed to give it) or the directory
+// isn't writable, the file is overwritten in place instead.
+pub fn write(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    write_with_mode(path, bytes, None)
+}
+
+// Like `write`, but the file ends up readable by its owner only, whatever it
+// was before. For the editor's own files, which hold copies of what is being
+// edited.
+pub fn write_private(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    write_with_mode(path, bytes, Some(0o600))
+}
+
+fn write_with_mode(path: &Path, bytes: &[u8], mode: Option<u32>) -> io::Result<()> {
+    let target = resolve(path);
+    let original = match fs::metadata(&target) {
+        Ok(metadata) => Some(metadata),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+        Err(e) => return Err(e),
+    };
+    if original.as_ref().is_some_and(|m| m.nlink() > 1) {
+        return write_in_place(&target, bytes, mode);
+    }
+
+    let temp = temp_path(&target);
+    let mut file = match OpenOptions::new().write(true).create_new(true).mode(mode.unwrap_or(0o666)).open(&temp) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return write_in_place(&target, bytes, mode),
+        Err(e) => return Err(e),
+    };
+
+    let written = (|| {
+        if mode.is_none() && let Some(original) = &original {
+            if fchown(&file, Some(original.uid()), Some(original.gid())).is_err() {
+                return Ok(false);
+            }
+            file.set_permissions(fs::Permissions::from_mode(original.mode()))?;
+        }
+        file.write_all(bytes)?;
+        file.sync_all()?;
+        fs::rename(&temp, &target)?;
+        Ok(true)
+    })();
+    drop(file);
+
+    match written {
+        Ok(true) => {
+            sync_dir(&target);
+            Ok(())
+        }
+        Ok(false) => {
+            let _ = fs::remove_file(&temp);
+            write_in_place(&target, bytes, mode)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+// The file a write to `path` should end up in. A dangling symlink is left as
+// is and written through.
+fn resolve(path: &Path) -> PathBuf {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn temp_path(target: &Path) -> PathBuf {
+    let name = target.file_name().map_or_else(Default::default, |name| name.to_string_lossy());
+    target.with_file_name(format!(".{}.oxide-{}.tmp", name, process::id()))
+}
+
+fn write_in_place(target: &Path, bytes: &[u8], mode: Option<u32>) -> io::Result<()> {
+    let mut file = File::create(target)?;
+    if let Some(mode) = mode {
+        file.set_permissions(fs::Permissions::from_mode(mode))?;
+    }
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+// Makes the rename itself durable. Not every filesystem supports syncing a
+// directory, and the data is already safe by now, so failures are ignored.
+fn sync_dir(target: &Path) {
+    let dir = match target.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Ok(dir) = File::open(dir) {
+        let _ = dir.sync_all();
+    }
+}
@@ -0,0 +1,194 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+use std::time::SystemTime;
+
+use encoding_rs::Encoding;
+use serde::{Deserialize, Serialize};
+
+use crate::buffer::{Buffer, LineEnding};
+use crate::save;
+use crate::undo_cache;
+
+// Unsaved changes are written to a swap file under ~/.cache/oxide/swap every
+// few seconds, so that they survive the terminal going away or a crash. The
+// file is named after a hash of the absolute path it belongs to and removed
+// again once the buffer is saved or the editor exits normally.
+#[derive(Serialize, Deserialize)]
+pub struct Swap<T = String> {
+    pub path: PathBuf,
+    pub pid: u32,
+    pub encoding: String,
+    pub bom: bool,
+    pub line_ending: LineEnding,
+    pub final_newline: bool,
+    pub cursor_row: usize,
+    pub cursor_col: usize,
+    pub text: T,
+}
+
+// Everything needed to write a swap file. Cheap to take since the buffer
+// shares its rope.
+pub struct Snapshot {
+    pub filename: String,
+    pub content: Buffer,
+    pub encoding: &'static Encoding,
+    pub bom: bool,
+    pub final_newline: bool,
+    pub cursor_row: usize,
+    pub cursor_col: usize,
+}
+
+// The newest snapshot of each modified buffer, for the panic hook to write out.
+static LATEST: Mutex<Vec<Snapshot>> = Mutex::new(Vec::new());
+
+// Swap files are written and removed on a thread of their own, since turning
+// a large buffer into one takes long enough to hold up typing. Jobs are done in
+// the order they were queued.
+enum Job {
+    Write(Snapshot),
+    Remove(String),
+    Done(Sender<()>),
+}
+
+impl Job {
+    fn filename(&self) -> Option<&String> {
+        match self {
+            Job::Write(snapshot) => Some(&snapshot.filename),
+            Job::Remove(filename) => Some(filename),
+            Job::Done(_) => None,
+        }
+    }
+}
+
+fn queue() -> &'static Sender<Job> {
+    static QUEUE: OnceLock<Sender<Job>> = OnceLock::new();
+    QUEUE.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || run(receiver));
+        sender
+    })
+}
+
+// A write is skipped when a later job for the same file is already waiting,
+// so a slow write doesn't leave a backlog of outdated ones behind it.
+fn run(receiver: Receiver<Job>) {
+    while let Ok(job) = receiver.recv() {
+        let mut jobs = vec![job];
+        jobs.extend(receiver.try_iter());
+        for (i, job) in jobs.iter().enumerate() {
+            match job {
+                Job::Write(snapshot) => {
+                    if !jobs[i + 1..].iter().any(|later| later.filename() == Some(&snapshot.filename)) {
+                        let _ = write(snapshot);
+                    }
+                }
+                Job::Remove(filename) => remove_now(filename),
+                Job::Done(done) => {
+                    let _ = done.send(());
+                }
+            }
+        }
+    }
+}
+
+// Writes the swap file for `snapshot` in the background.
+pub fn write_later(snapshot: Snapshot) {
+    let _ = queue().send(Job::Write(snapshot));
+}
+
+// Removes the swap file of `filename` once the writes queued before are done.
+pub fn remove(filename: &str) {
+    let _ = queue().send(Job::Remove(filename.to_string()));
+}
+
+// Blocks until everything queued so far has been written or removed.
+pub fn wait() {
+    let (done, finished) = mpsc::channel();
+    if queue().send(Job::Done(done)).is_ok() {
+        let _ = finished.recv();
+    }
+}
+
+fn absolute(filename: &str) -> io::Result<PathBuf> {
+    fs::canonicalize(filename).or_else(|_| std::path::absolute(filename))
+}
+
+fn swap_path(absolute: &Path) -> Option<PathBuf> {
+    let name = format!("{:016x}.swp", undo_cache::hash(absolute.as_os_str().as_encoded_bytes()));
+    Some(undo_cache::cache_dir()?.join("swap").join(name))
+}
+
+pub fn write(snapshot: &Snapshot) -> io::Result<()> {
+    let path = absolute(&snapshot.filename)?;
+    let Some(swap_path) = swap_path(&path) else { return Ok(()) };
+
+    let text = snapshot.content.text();
+    let swap = Swap {
+        path,
+        pid: process::id(),
+        encoding: snapshot.encoding.name().to_string(),
+        bom: snapshot.bom,
+        line_ending: snapshot.content.line_ending(),
+        final_newline: snapshot.final_newline,
+        cursor_row: snapshot.cursor_row,
+        cursor_col: snapshot.cursor_col,
+        text: text.as_str(),
+    };
+    undo_cache::create_private_dir(swap_path.parent().unwrap())?;
+    save::write_private(&swap_path, &serde_json::to_vec(&swap)?)
+}
+
+fn remove_now(filename: &str) {
+    if let Ok(path) = absolute(filename)
+        && let Some(swap_path) = swap_path(&path)
+    {
+        let _ = fs::remove_file(swap_path);
+    }
+}
+
+// The swap file left for `filename` along with when it was written, if it
+// holds changes newer than the file itself. Swap files older than the file
+// are out of date and removed, unless their editor is still running.
+pub fn load(filename: &str) -> Option<(Swap, SystemTime)> {
+    let path = absolute(filename).ok()?;
+    let swap_path = swap_path(&path)?;
+    let written = fs::metadata(&swap_path).ok()?.modified().ok()?;
+    let swap: Swap = serde_json::from_slice(&fs::read(&swap_path).ok()?).ok()?;
+
+    let file_written = fs::metadata(&path).ok().and_then(|m| m.modified().ok());
+    if swap.path != path || file_written.is_some_and(|time| time > written) {
+        if !is_running(swap.pid) {
+            let _ = fs::remove_file(swap_path);
+        }
+        return None;
+    }
+    Some((swap, written))
+}
+
+pub fn is_running(pid: u32) -> bool {
+    pid != process::id() && Path::new("/proc").join(pid.to_string()).exists()
+}
+
+// Keeps `snapshot` of `filename` around for `flush`, replacing the one before.
+// None once there is nothing unsaved.
+pub fn remember(filename: &str, snapshot: Option<Snapshot>) {
+    if let Ok(mut latest) = LATEST.lock() {
+        latest.retain(|s| s.filename != filename);
+        latest.extend(snapshot);
+    }
+}
+
+// Writes out the remembered snapshots. Called while panicking, so it gives up
+// rather than wait if they are being updated.
+pub fn flush() {
+    if let Ok(latest) = LATEST.try_lock() {
+        for snapshot in latest.iter() {
+            let _ = write(snapshot);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use std::io;
+use std::panic;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crossterm::cursor::Show;
+use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use crossterm::execute;
+use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode};
+use signal_hook::consts::{SIGHUP, SIGTERM, SIGTSTP};
+
+// Owns the terminal modes the editor runs in: raw mode, the alternate screen
+// and mouse capture. They are switched on when the guard is made and back off
+// when it is dropped or the program panics. SIGTERM and SIGHUP are caught and
+// only noted, so that the event loop can wind down through the same path.
+pub struct TerminalGuard {
+    terminate: Arc<AtomicBool>,
+}
+
+impl TerminalGuard {
+    pub fn new() -> io::Result<TerminalGuard> {
+        let terminate = Arc::new(AtomicBool::new(false));
+        signal_hook::flag::register(SIGTERM, Arc::clone(&terminate))?;
+        signal_hook::flag::register(SIGHUP, Arc::clone(&terminate))?;
+
+        // Restore first so the panic message lands on the normal screen.
+        let previous_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let _ = leave();
+            previous_hook(info);
+        }));
+
+        enter()?;
+        Ok(TerminalGuard { terminate })
+    }
+
+    pub fn terminate_requested(&self) -> bool {
+        self.terminate.load(Ordering::Relaxed)
+    }
+
+    // Stops the process the way ^Z does in a shell, with the terminal in its
+    // normal state while stopped. Returns once it is continued with `fg`, after
+    // which the screen needs a full redraw.
+    pub fn suspend(&self) -> io::Result<()> {
+        leave()?;
+        signal_hook::low_level::raise(SIGTSTP)?;
+        enter()
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = leave();
+    }
+}
+
+fn enter() -> io::Result<()> {
+    enable_raw_mode()?;
+    execute!(io::stderr(), EnterAlternateScreen, EnableMouseCapture)
+}
+
+fn leave() -> io::Result<()> {
+    let raw_mode = disable_raw_mode();
+    execute!(io::stderr(), LeaveAlternateScreen, DisableMouseCapture, Show)?;
+    raw_mode
+}
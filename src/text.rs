@@ -0,0 +1,194 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+use crate::encoding;
+
+// Cursor columns are byte offsets into a line that always sit on grapheme
+// cluster boundaries. These helpers move between boundaries and convert to and
+// from the other column units the editor deals with.
+
+pub const TAB_WIDTH: usize = 4;
+
+pub fn next_boundary(line: &str, col: usize) -> usize {
+    line[col..].graphemes(true).next().map_or(col, |g| col + g.len())
+}
+
+pub fn prev_boundary(line: &str, col: usize) -> usize {
+    line[..col].graphemes(true).next_back().map_or(col, |g| col - g.len())
+}
+
+fn single_char(grapheme: &str) -> Option<char> {
+    let mut chars = grapheme.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+fn control_char(grapheme: &str) -> Option<char> {
+    single_char(grapheme).filter(|&c| c.is_control() && c != '\t')
+}
+
+fn escaped_byte(grapheme: &str) -> Option<u8> {
+    single_char(grapheme).and_then(encoding::escaped_byte)
+}
+
+// How many terminal cells `grapheme` takes when drawn starting at `display_col`.
+// Tabs stretch to the next tab stop, control characters are drawn as ^X, bytes
+// that didn't decode as <0xFF> and zero-width graphemes get a visible
+// placeholder so the cursor can land on them.
+pub fn grapheme_width(grapheme: &str, display_col: usize) -> usize {
+    if grapheme == "\t" {
+        TAB_WIDTH - display_col % TAB_WIDTH
+    } else if control_char(grapheme).is_some() {
+        2
+    } else if escaped_byte(grapheme).is_some() {
+        6
+    } else {
+        grapheme.width().max(1)
+    }
+}
+
+pub fn render_grapheme(grapheme: &str, display_col: usize) -> Cow<'_, str> {
+    if grapheme == "\t" {
+        Cow::Owned(" ".repeat(grapheme_width(grapheme, display_col)))
+    } else if let Some(c) = control_char(grapheme) {
+        Cow::Owned(format!("^{}", ((c as u8) ^ 0x40) as char))
+    } else if let Some(byte) = escaped_byte(grapheme) {
+        Cow::Owned(format!("<0x{:02X}>", byte))
+    } else if grapheme.width() == 0 {
+        Cow::Borrowed("·")
+    } else {
+        Cow::Borrowed(grapheme)
+    }
+}
+
+// The line as it should be drawn, with every grapheme replaced by what
+// `render_grapheme` draws for it. Plain lines are returned untouched.
+pub fn expand(line: Cow<'_, str>) -> Cow<'_, str> {
+    if line.bytes().all(|b| (0x20..0x7f).contains(&b)) {
+        return line;
+    }
+    let mut expanded = String::with_capacity(line.len());
+    let mut display = 0;
+    for grapheme in line.graphemes(true) {
+        expanded.push_str(&render_grapheme(grapheme, display));
+        display += grapheme_width(grapheme, display);
+    }
+    Cow::Owned(expanded)
+}
+
+// The part of `line` that shows in display `columns`, drawn the way `expand`
+// draws it. A wide grapheme cut in half at the left edge is replaced by
+// spaces. Graphemes past the right edge aren't looked at, so only the start
+// of a very long line is ever walked.
+pub fn visible(line: &str, columns: Range<usize>) -> Cow<'_, str> {
+    let printable = |b: &u8| (0x20..0x7f).contains(b);
+    let end = columns.end.min(line.len());
+    if line.as_bytes()[..end].iter().all(printable) && line.as_bytes().get(end).is_none_or(printable) {
+        return Cow::Borrowed(&line[columns.start.min(end)..end]);
+    }
+    let mut visible = String::new();
+    let mut display = 0;
+    for grapheme in line.graphemes(true) {
+        if display >= columns.end {
+            break;
+        }
+        let width = grapheme_width(grapheme, display);
+        if display >= columns.start {
+            visible.push_str(&render_grapheme(grapheme, display));
+        } else if display + width > columns.start {
+            visible.push_str(&" ".repeat(display + width - columns.start));
+        }
+        display += width;
+    }
+    Cow::Owned(visible)
+}
+
+pub fn display_col(line: &str, col: usize) -> usize {
+    line[..col].graphemes(true).fold(0, |display, g| display + grapheme_width(g, display))
+}
+
+// The byte offset of the grapheme shown at `target` display columns, or the
+// end of the line if it is shorter than that.
+pub fn col_at_display(line: &str, target: usize) -> usize {
+    let mut width = 0;
+    for (col, grapheme) in line.grapheme_indices(true) {
+        let next = width + grapheme_width(grapheme, width);
+        if next > target {
+            return col;
+        }
+        width = next;
+    }
+    line.len()
+}
+
+// Byte offsets at which each visual row of `line` starts when soft-wrapped to
+// `width` columns. Rows break after whitespace where possible, and only in the
+// middle of a word when it doesn't fit on a row of its own.
+pub fn wrap_points(line: &str, width: usize) -> Vec<usize> {
+    let mut points = vec![0];
+    let mut row_start = 0;
+    let mut display = 0;
+    let mut last_break = 0;
+
+    for (col, grapheme) in line.grapheme_indices(true) {
+        if display + grapheme_width(grapheme, display) > width && col > row_start {
+            row_start = if last_break > row_start { last_break } else { col };
+            points.push(row_start);
+            display = display_col(&line[row_start..], col - row_start);
+        }
+        display += grapheme_width(grapheme, display);
+        if grapheme.chars().all(char::is_whitespace) {
+            last_break = col + grapheme.len();
+        }
+    }
+    points
+}
+
+// Which of the rows returned by `wrap_points` byte offset `col` falls on.
+pub fn wrap_row(points: &[usize], col: usize) -> usize {
+    points.partition_point(|&start| start <= col) - 1
+}
+
+pub fn char_col(line: &str, col: usize) -> usize {
+    line[..col].chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_after_spaces() {
+        assert_eq!(wrap_points("", 10), [0]);
+        assert_eq!(wrap_points("short", 10), [0]);
+        assert_eq!(wrap_points("one two three", 7), [0, 4, 8]);
+        assert_eq!(wrap_points("one two three", 8), [0, 8]);
+        assert_eq!(wrap_points("aaaa bbbb", 5), [0, 5]);
+    }
+
+    #[test]
+    fn breaks_words_longer_than_a_row() {
+        assert_eq!(wrap_points("abcdefghij", 4), [0, 4, 8]);
+        assert_eq!(wrap_points("ab cdefghij", 4), [0, 3, 7]);
+    }
+
+    #[test]
+    fn counts_wide_characters_and_tabs() {
+        assert_eq!(wrap_points("日本語", 4), [0, 6]);
+        assert_eq!(wrap_points("a\tb", 4), [0, 2]);
+    }
+
+    #[test]
+    fn finds_the_row_of_a_column() {
+        let points = wrap_points("one two three", 7);
+        assert_eq!(wrap_row(&points, 0), 0);
+        assert_eq!(wrap_row(&points, 3), 0);
+        assert_eq!(wrap_row(&points, 4), 1);
+        assert_eq!(wrap_row(&points, 13), 2);
+    }
+}
@@ -1,120 +1,103 @@
+use std::borrow::Cow;
+use std::ops::Range;
+use std::rc::Rc;
+
 use ratatui::{Frame, layout::{Alignment, Constraint, Direction, Layout, Rect}, style::{Color, Modifier, Style}, text::{Line, Span}, widgets::{Block, Borders, Clear, Paragraph}};
 
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
 use crate::app::{App, InputMode};
+use crate::document::{Document, View};
+use crate::explorer::Explorer;
+use crate::pane::{Orientation, PaneLayout};
+use crate::render_cache::RenderCache;
+use crate::text;
 
 
 pub fn ui(frame: &mut Frame, app: &App) {
+    let doc = app.doc();
 
-    let chunks = Layout::default()
-        .direction(Direction::Vertical)
-        .constraints([
-            Constraint::Length(1),
-            Constraint::Min(1),
-            Constraint::Length(1),
-        ])
-        .split(frame.area());
-
-    let modified_indicator = if app.modified { " ●" } else { "" };
-    
-
-    let title = Paragraph::new(Line::from(vec![
-        Span::styled("Oxide", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
-        Span::styled(" │ ", Style::default().fg(Color::DarkGray)),
-        Span::styled(&app.filename, Style::default().fg(Color::White)),
-        Span::styled(modified_indicator, Style::default().fg(Color::Yellow)),
-    ])).alignment(Alignment::Center).style(Style::default().bg(Color::Rgb(30, 30, 30)));
-
-    let editor_block = Block::default().borders(Borders::ALL).style(Style::default());
-  
-    let line_number_width = app.content.len().to_string().len().max(2);
-    
-    let editor_text: Vec<Line> = app.content.iter().enumerate().map(|(i, line)| {
-        let is_cursor_line = i == app.cursor_row;
-        let line_num_style = if is_cursor_line {
-            Style::default().fg(Color::Yellow)
+    let chunks = screen_chunks(frame.area());
 
+    let tab_bar = TabBar::new(app, chunks[0].width);
+    let mut title_spans = vec![
+        Span::styled(" Oxide", Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD)),
+        Span::styled(" │", Style::default().fg(Color::DarkGray)),
+    ];
+    if tab_bar.more_left || tab_bar.more_right {
+        title_spans.push(Span::styled(if tab_bar.more_left { "‹" } else { " " }, Style::default().fg(Color::Yellow)));
+    }
+    for tab in &tab_bar.tabs {
+        let style = if tab.index == app.active {
+            Style::default().fg(Color::White).bg(Color::Rgb(60, 60, 60)).add_modifier(Modifier::BOLD)
         } else {
-            Style::default().fg(Color::DarkGray)
-
-        };
- 
-        let mut spans = vec![
-            Span::styled(format!(" {:>width$} │ ", i + 1, width = line_number_width),line_num_style,),
-        ];
-
-        let sel = app.selection.map(|s| app.normalize_selection(s));
-        let in_selection = |row: usize, col: usize| -> bool {
-            if let Some((sr, sc, er, ec)) = sel {
-                if row > sr && row < er { return true; }
-
-                if row == sr && row == er { return col >= sc && col < ec; }
-
-                if row == sr { return col >= sc; }
-
-                if row == er { return col < ec; }
-            }
-            false
+            Style::default().fg(Color::Rgb(150, 150, 150))
         };
-
-
-        if is_cursor_line || sel.map_or(false, |(sr, _, er, _)| i >= sr && i <= er) {
-            let mut col = 0;
-            for ch in line.chars() {
-                let is_cursor = is_cursor_line && col == app.cursor_col;
-
-                let is_selected = in_selection(i, col);
-                
-                let style = if is_cursor {
-                    Style::default().bg(Color::White).fg(Color::Black)
-                } else if is_selected {
-                    Style::default().bg(Color::Rgb(60, 60, 120)).fg(Color::White)
-                } else {
-                    Style::default()
-                };
-
-                spans.push(Span::styled(ch.to_string(), style));
-                col += 1;
-            }
-            if is_cursor_line && app.cursor_col >= line.len() {
-                spans.push(Span::styled(" ", Style::default().bg(Color::White).fg(Color::Black)));
-            }
-        } else {
-            spans.push(Span::raw(line.as_str()));
-
+        title_spans.push(Span::styled(format!(" {}", tab.name), style));
+        if app.documents[tab.index].read_only {
+            title_spans.push(Span::styled(" RO", style.fg(Color::Rgb(255, 110, 110))));
         }
-
-        Line::from(spans)
-    }).collect();
-
-
-    let editor = Paragraph::new(editor_text)
-        .block(editor_block)
-        .scroll((app.scroll_offset as u16, 0));
+        if app.documents[tab.index].modified {
+            title_spans.push(Span::styled(" ●", style.fg(Color::Yellow)));
+        }
+        title_spans.push(Span::styled(" ", style));
+        title_spans.push(Span::styled("│", Style::default().fg(Color::DarkGray)));
+    }
+    let title = Paragraph::new(Line::from(title_spans)).style(Style::default().bg(Color::Rgb(30, 30, 30)));
 
     let key_style = Style::default().fg(Color::Rgb(30, 30, 30)).bg(Color::Rgb(100, 100, 100)).add_modifier(Modifier::BOLD);
     
     let label_style = Style::default().fg(Color::Rgb(200, 200, 200));
     
-    let footer = Paragraph::new(Line::from(vec![
-        Span::styled(" ^S ", key_style),
-        Span::styled("Save ", label_style),
-        Span::styled(" ^F ", key_style),
-        Span::styled("Find ", label_style),
-        Span::styled(" ^G ", key_style),
-        Span::styled("GoTo ", label_style),
-        Span::styled(" ^Z ", key_style),
-        Span::styled("Undo ", label_style),
-        Span::styled(" ^Q ", key_style),
-        Span::styled("Quit ", label_style),
+    // A status message takes the place of the key hints until the next key press.
+    let mut footer_spans = match &app.status {
+        Some(status) => {
+            let color = if status.is_error { Color::Rgb(255, 110, 110) } else { Color::Rgb(130, 210, 130) };
+            vec![Span::styled(format!(" {} ", status.text), Style::default().fg(color).add_modifier(Modifier::BOLD))]
+        }
+        None => vec![
+            Span::styled(" ^S ", key_style),
+            Span::styled("Save ", label_style),
+            Span::styled(" ^F ", key_style),
+            Span::styled("Find ", label_style),
+            Span::styled(" ^G ", key_style),
+            Span::styled("GoTo ", label_style),
+            Span::styled(" M-Z ", key_style),
+            Span::styled("Undo ", label_style),
+            Span::styled(" ^Q ", key_style),
+            Span::styled("Quit ", label_style),
+        ],
+    };
+    footer_spans.extend([
+        Span::styled(
+            format!(" Ln {}, Col {} ", doc.cursor_row + 1, text::char_col(&doc.content.line(doc.cursor_row), doc.cursor_col) + 1),
+            Style::default().fg(Color::Rgb(150, 150, 150)),
+        ),
         Span::styled(
-            format!(" Ln {}, Col {} ", app.cursor_row + 1, app.cursor_col + 1),
+            format!(" {}{} ", doc.line_ending().name(), if doc.final_newline { "" } else { " noeol" }),
             Style::default().fg(Color::Rgb(150, 150, 150)),
         ),
-    ]))
-    .style(Style::default().bg(Color::Rgb(45, 45, 45)));
+        Span::styled(
+            format!(" {}{} ", doc.encoding.name(), if doc.bom { " BOM" } else { "" }),
+            Style::default().fg(Color::Rgb(150, 150, 150)),
+        ),
+    ]);
+    let footer = Paragraph::new(Line::from(footer_spans)).style(Style::default().bg(Color::Rgb(45, 45, 45)));
 
     frame.render_widget(title, chunks[0]);
-    frame.render_widget(editor, chunks[1]);
+    if tab_bar.more_right {
+        let arrow = Rect::new(chunks[0].right().saturating_sub(1), chunks[0].y, 1, 1);
+        frame.render_widget(Paragraph::new(Span::styled("›", Style::default().fg(Color::Yellow))), arrow);
+    }
+    if let Some(area) = sidebar_area(app, frame.area())
+        && let Some(explorer) = &app.explorer
+    {
+        render_explorer(frame, app, explorer, area);
+    }
+    for (pane, &area) in pane_areas(app, frame.area()).iter().enumerate() {
+        render_pane(frame, app, pane, area);
+    }
     frame.render_widget(footer, chunks[2]);
 
 
@@ -154,24 +137,600 @@ pub fn ui(frame: &mut Frame, app: &App) {
 
             frame.render_widget(goto_text, area);
         }
-        InputMode::ConfirmQuit => {
+        InputMode::ReopenEncoding | InputMode::SaveEncoding => {
+            let (prompt, title) = if app.input_mode == InputMode::ReopenEncoding {
+                ("Reopen with encoding: ", if doc.modified { " Discards unsaved changes " } else { "" })
+            } else {
+                ("Save with encoding: ", "")
+            };
+
+            let area = centered_rect(50, 3, frame.area());
+            frame.render_widget(Clear, area);
+
+            let encoding_block = Block::default()
+                .borders(Borders::ALL)
+                .title(title)
+                .style(Style::default().bg(Color::Rgb(40, 40, 40)));
+            let encoding_text = Paragraph::new(Line::from(vec![
+                Span::styled(prompt, Style::default().fg(Color::Cyan)),
+                Span::styled(&app.input_buffer, Style::default().fg(Color::White)),
+                Span::styled("▌", Style::default().fg(Color::White)),
+            ])).block(encoding_block);
+
+            frame.render_widget(encoding_text, area);
+        }
+        InputMode::ExplorerCreate | InputMode::ExplorerRename => {
+            let prompt = if app.input_mode == InputMode::ExplorerCreate { "New file: " } else { "Rename to: " };
+            let title = if app.input_mode == InputMode::ExplorerCreate { " End with / for a directory " } else { "" };
+
+            let area = centered_rect(60, 3, frame.area());
+            frame.render_widget(Clear, area);
+
+            let name_block = Block::default()
+                .borders(Borders::ALL)
+                .title(title)
+                .style(Style::default().bg(Color::Rgb(40, 40, 40)));
+            let name_text = Paragraph::new(Line::from(vec![
+                Span::styled(prompt, Style::default().fg(Color::Cyan)),
+                Span::styled(&app.input_buffer, Style::default().fg(Color::White)),
+                Span::styled("▌", Style::default().fg(Color::White)),
+            ])).block(name_block);
+
+            frame.render_widget(name_text, area);
+        }
+        InputMode::SaveAs => {
+            let area = centered_rect(60, 3, frame.area());
+            frame.render_widget(Clear, area);
+
+            let name_block = Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().bg(Color::Rgb(40, 40, 40)));
+            let name_text = Paragraph::new(Line::from(vec![
+                Span::styled("Save as: ", Style::default().fg(Color::Cyan)),
+                Span::styled(&app.input_buffer, Style::default().fg(Color::White)),
+                Span::styled("▌", Style::default().fg(Color::White)),
+            ])).block(name_block);
+
+            frame.render_widget(name_text, area);
+        }
+        InputMode::ConfirmDelete => {
+            let question = match app.explorer.as_ref().and_then(|explorer| explorer.selected()) {
+                Some(entry) if entry.is_dir => format!("Delete '{}' and everything in it? (y/n)", entry.name),
+                Some(entry) => format!("Delete '{}'? (y/n)", entry.name),
+                None => String::new(),
+            };
+            let area = centered_rect((question.width() as u16 + 4).max(45), 3, frame.area());
+            frame.render_widget(Clear, area);
+
+            let confirm_block = Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().bg(Color::Rgb(60, 40, 40)));
+            let confirm_text = Paragraph::new(Line::from(vec![
+                Span::styled(question, Style::default().fg(Color::Yellow)),
+            ])).block(confirm_block).alignment(Alignment::Center);
+
+            frame.render_widget(confirm_text, area);
+        }
+        InputMode::UndoTree => {
+            let rows = doc.history.layout();
+            let height = (rows.len() as u16 + 2).min(frame.area().height.saturating_sub(4)).max(3);
+            let area = centered_rect(60, height, frame.area());
+            frame.render_widget(Clear, area);
+
+            let current = doc.history.current();
+            let tree_text: Vec<Line> = rows.iter().enumerate().map(|(i, row)| {
+                let marker = if row.node == current { "● " } else { "○ " };
+                let style = if i == app.undo_tree_index {
+                    Style::default().bg(Color::Rgb(60, 60, 120)).fg(Color::White)
+                } else {
+                    Style::default().fg(Color::White)
+                };
+                Line::from(vec![
+                    Span::styled(row.graph.clone(), Style::default().fg(Color::DarkGray)),
+                    Span::styled(marker, Style::default().fg(Color::Yellow)),
+                    Span::styled(format!("{:<4} {} ", row.node, doc.history.describe(row.node)), style),
+                    Span::styled(doc.history.age(row.node), Style::default().fg(Color::DarkGray)),
+                ])
+            }).collect();
+
+            let visible = area.height.saturating_sub(2) as usize;
+            let scroll = (app.undo_tree_index + 1).saturating_sub(visible);
+
+            let tree_block = Block::default()
+                .borders(Borders::ALL)
+                .title(" Undo history ")
+                .style(Style::default().bg(Color::Rgb(40, 40, 40)));
+            let tree = Paragraph::new(tree_text).block(tree_block).scroll((scroll as u16, 0));
+
+            frame.render_widget(tree, area);
+        }
+        InputMode::ConfirmBinaryEdit => {
+            let area = centered_rect(45, 3, frame.area());
+            frame.render_widget(Clear, area);
+
+            let confirm_block = Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().bg(Color::Rgb(60, 40, 40)));
+            let confirm_text = Paragraph::new(Line::from(vec![
+                Span::styled("File looks binary! Edit anyway? (y/n)", Style::default().fg(Color::Yellow)),
+            ])).block(confirm_block).alignment(Alignment::Center);
+
+            frame.render_widget(confirm_text, area);
+        }
+        InputMode::ExternalChange => {
+            let area = centered_rect(60, 4, frame.area());
+            frame.render_widget(Clear, area);
+
+            let change_block = Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().bg(Color::Rgb(60, 40, 40)));
+            let change_text = Paragraph::new(vec![
+                Line::from(Span::styled("File changed on disk!", Style::default().fg(Color::Yellow))),
+                Line::from(vec![
+                    Span::styled("(r)", Style::default().fg(Color::Cyan)),
+                    Span::raw("eload  "),
+                    Span::styled("(o)", Style::default().fg(Color::Cyan)),
+                    Span::raw("verwrite  "),
+                    Span::styled("(d)", Style::default().fg(Color::Cyan)),
+                    Span::raw("iff  "),
+                    Span::styled("Esc", Style::default().fg(Color::Cyan)),
+                    Span::raw(" keep editing"),
+                ]),
+            ]).block(change_block).alignment(Alignment::Center);
+
+            frame.render_widget(change_text, area);
+        }
+        InputMode::DiskDiff => {
+            let area = frame.area();
+            let area = centered_rect(area.width.saturating_sub(8), area.height.saturating_sub(4), area);
+            frame.render_widget(Clear, area);
+
+            let diff_text: Vec<Line> = app.disk_diff.iter().map(|line| {
+                let color = match line.chars().next() {
+                    Some('+') if !line.starts_with("+++") => Color::Green,
+                    Some('-') if !line.starts_with("---") => Color::Red,
+                    Some('@') => Color::Cyan,
+                    _ => Color::White,
+                };
+                Line::from(Span::styled(line.as_str(), Style::default().fg(color)))
+            }).collect();
+
+            let diff_block = Block::default()
+                .borders(Borders::ALL)
+                .title(" Changes on disk → in editor (Esc to go back) ")
+                .style(Style::default().bg(Color::Rgb(40, 40, 40)));
+            let diff = Paragraph::new(diff_text).block(diff_block).scroll((app.disk_diff_scroll as u16, 0));
+
+            frame.render_widget(diff, area);
+        }
+        InputMode::BufferList => {
+            let matches = app.buffer_matches();
+            let height = (matches.len() as u16 + 3).min(frame.area().height.saturating_sub(4)).max(4);
+            let area = centered_rect(60, height, frame.area());
+            frame.render_widget(Clear, area);
+
+            let mut list_text = vec![Line::from(vec![
+                Span::styled("Buffer: ", Style::default().fg(Color::Cyan)),
+                Span::styled(&app.input_buffer, Style::default().fg(Color::White)),
+                Span::styled("▌", Style::default().fg(Color::White)),
+            ])];
+            let visible = area.height.saturating_sub(3) as usize;
+            let scroll = (app.buffer_list_index + 1).saturating_sub(visible);
+            for (i, &index) in matches.iter().enumerate().skip(scroll).take(visible) {
+                let document = &app.documents[index];
+                let style = if i == app.buffer_list_index {
+                    Style::default().bg(Color::Rgb(60, 60, 120)).fg(Color::White)
+                } else {
+                    Style::default().fg(Color::White)
+                };
+                list_text.push(Line::from(vec![
+                    Span::styled(if document.modified { "● " } else { "  " }, Style::default().fg(Color::Yellow)),
+                    Span::styled(document.label(), style),
+                ]));
+            }
 
+            let list_block = Block::default()
+                .borders(Borders::ALL)
+                .title(" Buffers ")
+                .style(Style::default().bg(Color::Rgb(40, 40, 40)));
+            frame.render_widget(Paragraph::new(list_text).block(list_block), area);
+        }
+        InputMode::FindFile => {
+            let Some(finder) = &app.finder else { return };
+            let area = frame.area();
+            let area = centered_rect(area.width.saturating_sub(8), area.height.saturating_sub(4), area);
+            frame.render_widget(Clear, area);
+            let parts = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(45), Constraint::Percentage(55)])
+                .split(area);
 
+            let mut list_text = vec![Line::from(vec![
+                Span::styled("File: ", Style::default().fg(Color::Cyan)),
+                Span::styled(&app.input_buffer, Style::default().fg(Color::White)),
+                Span::styled("▌", Style::default().fg(Color::White)),
+            ])];
+            let visible = parts[0].height.saturating_sub(3) as usize;
+            let scroll = (finder.selected + 1).saturating_sub(visible);
+            for (i, &index) in finder.matches.iter().enumerate().skip(scroll).take(visible) {
+                let style = if i == finder.selected {
+                    Style::default().bg(Color::Rgb(60, 60, 120)).fg(Color::White)
+                } else {
+                    Style::default().fg(Color::White)
+                };
+                list_text.push(Line::from(Span::styled(finder.file(index), style)));
+            }
+
+            let list_block = Block::default()
+                .borders(Borders::ALL)
+                .title(if finder.scanning() && finder.matches.is_empty() {
+                    " Open file (listing…) ".to_string()
+                } else {
+                    format!(" Open file ({}) ", finder.matches.len())
+                })
+                .style(Style::default().bg(Color::Rgb(40, 40, 40)));
+            frame.render_widget(Paragraph::new(list_text).block(list_block), parts[0]);
+
+            let preview_text: Vec<Line> = finder.preview.iter()
+                .take(parts[1].height.saturating_sub(2) as usize)
+                .map(|line| Line::from(Span::styled(text::expand(Cow::Borrowed(line)).into_owned(), Style::default().fg(Color::Rgb(200, 200, 200)))))
+                .collect();
+            let preview_block = Block::default()
+                .borders(Borders::ALL)
+                .title(" Preview ")
+                .style(Style::default().bg(Color::Rgb(40, 40, 40)));
+            frame.render_widget(Paragraph::new(preview_text).block(preview_block), parts[1]);
+        }
+        InputMode::ConfirmClose => {
             let area = centered_rect(45, 3, frame.area());
             frame.render_widget(Clear, area);
-            
+
             let confirm_block = Block::default()
                 .borders(Borders::ALL)
                 .style(Style::default().bg(Color::Rgb(60, 40, 40)));
             let confirm_text = Paragraph::new(Line::from(vec![
-                Span::styled("Unsaved changes! Quit? (y/n)", Style::default().fg(Color::Yellow)),
+                Span::styled("Unsaved changes! Close buffer? (y/n)", Style::default().fg(Color::Yellow)),
             ])).block(confirm_block).alignment(Alignment::Center);
 
             frame.render_widget(confirm_text, area);
         }
+        InputMode::ConfirmQuit => {
+            let modified = app.modified_buffers();
+            let height = (modified.len() as u16 + 4).min(frame.area().height.saturating_sub(4)).max(5);
+            let area = centered_rect(50, height, frame.area());
+            frame.render_widget(Clear, area);
+
+            let mut confirm_text = vec![Line::from(Span::styled("Unsaved changes in:", Style::default().fg(Color::Yellow)))];
+            confirm_text.extend(modified.iter().map(|&name| Line::from(Span::styled(name, Style::default().fg(Color::White)))));
+            confirm_text.push(Line::from(Span::styled("Quit anyway? (y/n)", Style::default().fg(Color::Yellow))));
+
+            let confirm_block = Block::default()
+                .borders(Borders::ALL)
+                .style(Style::default().bg(Color::Rgb(60, 40, 40)));
+            let confirm = Paragraph::new(confirm_text).block(confirm_block).alignment(Alignment::Center);
+
+            frame.render_widget(confirm, area);
+        }
+
+        InputMode::Normal | InputMode::Explorer => {}
+    }
+}
+
+// The title row, the editor area and the footer.
+fn screen_chunks(area: Rect) -> Rc<[Rect]> {
+    Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(1),
+            Constraint::Min(1),
+            Constraint::Length(1),
+        ])
+        .split(area)
+}
+
+// The widest the file tree sidebar gets.
+const SIDEBAR_WIDTH: u16 = 32;
+
+// The sidebar, when it is shown, and what is left of the editor area for
+// the panes.
+fn editor_areas(app: &App, area: Rect) -> (Option<Rect>, Rect) {
+    let editor = screen_chunks(area)[1];
+    if !app.show_explorer {
+        return (None, editor);
+    }
+    let parts = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Length((editor.width / 3).min(SIDEBAR_WIDTH)), Constraint::Min(1)])
+        .split(editor);
+    (Some(parts[0]), parts[1])
+}
+
+pub fn sidebar_area(app: &App, area: Rect) -> Option<Rect> {
+    editor_areas(app, area).0
+}
+
+// Where each pane goes on a screen of size `area`, by pane index.
+pub fn pane_areas(app: &App, area: Rect) -> Vec<Rect> {
+    let mut areas = vec![Rect::default(); app.panes.len()];
+    split_area(&app.layout, editor_areas(app, area).1, &mut areas);
+    areas
+}
+
+fn render_explorer(frame: &mut Frame, app: &App, explorer: &Explorer, area: Rect) {
+    let focused = matches!(app.input_mode, InputMode::Explorer | InputMode::ExplorerCreate | InputMode::ExplorerRename | InputMode::ConfirmDelete);
+    let height = area.height.saturating_sub(2) as usize;
+
+    let lines: Vec<Line> = explorer.entries.iter().enumerate().skip(explorer.scroll).take(height).map(|(i, entry)| {
+        let marker = match (entry.is_dir, entry.expanded) {
+            (true, true) => "▾ ",
+            (true, false) => "▸ ",
+            (false, _) => "  ",
+        };
+        let mut style = if entry.is_dir {
+            Style::default().fg(Color::Rgb(110, 160, 230)).add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(Color::White)
+        };
+        if i == explorer.selected {
+            style = style.bg(if focused { Color::Rgb(60, 60, 120) } else { Color::Rgb(50, 50, 50) });
+        }
+        Line::from(vec![
+            Span::raw("  ".repeat(entry.depth)),
+            Span::styled(marker, Style::default().fg(Color::DarkGray)),
+            Span::styled(entry.name.as_str(), style),
+        ])
+    }).collect();
+
+    let border = if focused { Color::Cyan } else { Color::DarkGray };
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .border_style(Style::default().fg(border))
+        .title(" Files ");
+    frame.render_widget(Paragraph::new(lines).block(block), area);
+}
+
+fn split_area(layout: &PaneLayout, area: Rect, areas: &mut [Rect]) {
+    match layout {
+        PaneLayout::Pane(pane) => areas[*pane] = area,
+        PaneLayout::Split { orientation, percent, first, second } => {
+            let direction = match orientation {
+                Orientation::SideBySide => Direction::Horizontal,
+                Orientation::Stacked => Direction::Vertical,
+            };
+            let parts = Layout::default()
+                .direction(direction)
+                .constraints([Constraint::Percentage(*percent), Constraint::Percentage(100 - percent)])
+                .split(area);
+            split_area(first, parts[0], areas);
+            split_area(second, parts[1], areas);
+        }
+    }
+}
+
+// The document shown in `pane` and the view it is shown with.
+pub fn pane_view(app: &App, pane: usize) -> (&Document, View) {
+    let document = &app.documents[app.panes[pane].document];
+    let view = if pane == app.focus { document.view() } else { app.panes[pane].view };
+    (document, view)
+}
+
+fn render_pane(frame: &mut Frame, app: &App, pane: usize, area: Rect) {
+    let (doc, view) = pane_view(app, pane);
+    let focused = pane == app.focus;
+
+    // Once there is more than one pane each is titled with its file, and
+    // the focused one stands out.
+    let mut editor_block = Block::default().borders(Borders::ALL).style(Style::default());
+    if app.panes.len() > 1 {
+        let border = if focused { Color::Cyan } else { Color::DarkGray };
+        editor_block = editor_block
+            .border_style(Style::default().fg(border))
+            .title(Line::from(vec![
+                Span::styled(format!(" {}", doc.name()), Style::default().fg(Color::White)),
+                Span::styled(if doc.read_only { " RO" } else { "" }, Style::default().fg(Color::Rgb(255, 110, 110))),
+                Span::styled(if doc.modified { " ● " } else { " " }, Style::default().fg(Color::Yellow)),
+            ]));
+    }
+
+    let line_number_width = doc.line_number_width();
+    let gutter = |label: String, is_cursor_line: bool| {
+        let line_num_style = if is_cursor_line {
+            Style::default().fg(Color::Yellow)
+        } else {
+            Style::default().fg(Color::DarkGray)
+        };
+        Span::styled(format!(" {:>width$} │ ", label, width = line_number_width), line_num_style)
+    };
+
+    let height = area.height.saturating_sub(2) as usize;
+    let width = (area.width as usize).saturating_sub(2 + doc.gutter_width());
+    let skip = if view.soft_wrap { 0 } else { view.h_scroll_offset };
+    let cursor = focused.then_some((view.cursor_row, view.cursor_col));
+    let sel = view.selection.map(|s| doc.normalize_selection(s));
+    let mut cache = app.panes[pane].render_cache.borrow_mut();
+    let mut editor_text: Vec<Line> = Vec::with_capacity(height);
+    let (mut row, mut sub_row) = (view.scroll_offset, view.scroll_sub_row);
+
+    while editor_text.len() < height && row < doc.content.len_lines() {
+        let line = doc.content.line(row);
+        let points = if view.soft_wrap { doc.wrap_points_at(row, view.wrap_width) } else { vec![0] };
+        for (i, &start) in points.iter().enumerate().skip(sub_row) {
+            if editor_text.len() == height {
+                break;
+            }
+            let end = points.get(i + 1).copied().unwrap_or(line.len());
+            let label = if i == 0 { (row + 1).to_string() } else { "↪".to_string() };
+
+            let mut spans = vec![gutter(label, row == view.cursor_row)];
+            spans.extend(row_spans(cursor, sel, &mut cache, row, &line, start..end, skip..skip + width));
+            editor_text.push(Line::from(spans));
+        }
+        row += 1;
+        sub_row = 0;
+    }
+    cache.end_frame();
+
+    frame.render_widget(Paragraph::new(editor_text).block(editor_block), area);
+}
+
+// Where the tabs begin in the title row, after " Oxide │".
+const TABS_START: u16 = 8;
+
+pub struct Tab {
+    pub index: usize,
+    pub name: String,
+    // The column the tab starts at and how many it takes, separator included.
+    pub x: u16,
+    pub width: u16,
+}
+
+// The tabs that fit in a title row `width` columns wide. When they don't all
+// fit, a column is kept at either end for arrows showing there are more, and
+// the tabs are scrolled just far enough to keep the active one in view.
+pub struct TabBar {
+    pub tabs: Vec<Tab>,
+    pub more_left: bool,
+    pub more_right: bool,
+}
+
+impl TabBar {
+    pub fn new(app: &App, width: u16) -> TabBar {
+        // Summed as usize since a few thousand open buffers add up to more
+        // columns than a u16 holds.
+        let tabs: Vec<(String, usize)> = app.documents.iter().map(|document| {
+            let name = document.name();
+            let width = name.width() + if document.modified { 5 } else { 3 } + if document.read_only { 3 } else { 0 };
+            (name, width)
+        }).collect();
+
+        let mut space = width.saturating_sub(TABS_START) as usize;
+        let mut x = TABS_START as usize;
+        let mut start = 0;
+        let overflow = tabs.iter().map(|&(_, width)| width).sum::<usize>() > space;
+        if overflow {
+            space = space.saturating_sub(2);
+            x += 1;
+            let widths = |range: std::ops::RangeInclusive<usize>| tabs[range].iter().map(|&(_, width)| width).sum::<usize>();
+            while start < app.active && widths(start..=app.active) > space {
+                start += 1;
+            }
+        }
+
+        let mut bar = TabBar { tabs: Vec::new(), more_left: start > 0, more_right: false };
+        let end = x + space;
+        for (index, (name, width)) in tabs.into_iter().enumerate().skip(start) {
+            if x + width > end && index > app.active {
+                bar.more_right = true;
+                break;
+            }
+            let tab_x = x.min(u16::MAX as usize) as u16;
+            let tab_width = width.min((u16::MAX - tab_x) as usize) as u16;
+            bar.tabs.push(Tab { index, name, x: tab_x, width: tab_width });
+            x += width;
+        }
+        bar
+    }
+
+    // The buffer whose tab is at `column`.
+    pub fn tab_at(&self, column: u16) -> Option<usize> {
+        self.tabs.iter().find(|tab| column >= tab.x && column < tab.x + tab.width).map(|tab| tab.index)
+    }
+
+    // Like `tab_at`, but the arrows count too, standing for the first tab
+    // hidden on their side.
+    pub fn buffer_at(&self, column: u16, width: u16) -> Option<usize> {
+        if let Some(index) = self.tab_at(column) {
+            return Some(index);
+        }
+        if self.more_left && column == TABS_START {
+            return Some(self.tabs.first()?.index - 1);
+        }
+        if self.more_right && column + 1 == width {
+            return Some(self.tabs.last()?.index + 1);
+        }
+        None
+    }
+}
+
+// Spans for the part of line `row` covering bytes `range`, one visual row,
+// clipped to the display `columns` on screen. Rows without the cursor or
+// selection on them come from the pane's render cache. `cursor` is only
+// given for the focused pane.
+fn row_spans(cursor: Option<(usize, usize)>, sel: Option<(usize, usize, usize, usize)>, cache: &mut RenderCache, row: usize, line: &str, range: Range<usize>, columns: Range<usize>) -> Vec<Span<'static>> {
+    if cursor.is_some_and(|(cursor_row, _)| cursor_row == row) || sel.is_some_and(|(sr, _, er, _)| row >= sr && row <= er) {
+        let cursor = cursor.filter(|&(cursor_row, _)| cursor_row == row).map(|(_, col)| col);
+        return line_spans(cursor, sel, row, &line[range.clone()], range.start, range.end == line.len(), columns);
+    }
+
+    let segment = &line[range];
+    vec![cache.span(segment, columns.start, columns.len(), || Span::raw(text::visible(segment, columns).into_owned()))]
+}
+
+// Spans for `segment`, the part of line `row` starting at byte `offset`, as
+// far as it shows in display `columns`. Neighbouring graphemes styled the
+// same share a span. `cursor` is the column of the cursor if it is on this
+// line.
+fn line_spans(cursor: Option<usize>, sel: Option<(usize, usize, usize, usize)>, row: usize, segment: &str, offset: usize, at_line_end: bool, columns: Range<usize>) -> Vec<Span<'static>> {
+    let in_selection = |col: usize| -> bool {
+        if let Some((sr, sc, er, ec)) = sel {
+            if row > sr && row < er { return true; }
+
+            if row == sr && row == er { return col >= sc && col < ec; }
 
-        InputMode::Normal => {}
+            if row == sr { return col >= sc; }
+
+            if row == er { return col < ec; }
+        }
+        false
+    };
+
+    let mut spans = Vec::new();
+    let mut run = String::new();
+    let mut run_style = Style::default();
+    let mut display_col = 0;
+    let mut reached_end = true;
+    for (i, grapheme) in segment.grapheme_indices(true) {
+        if display_col >= columns.end {
+            reached_end = false;
+            break;
+        }
+        let col = offset + i;
+        let width = text::grapheme_width(grapheme, display_col);
+        let start = display_col;
+        display_col += width;
+        if display_col <= columns.start {
+            continue;
+        }
+
+        let is_cursor = cursor == Some(col);
+
+        let is_selected = in_selection(col);
+
+        let style = if is_cursor {
+            Style::default().bg(Color::White).fg(Color::Black)
+        } else if is_selected {
+            Style::default().bg(Color::Rgb(60, 60, 120)).fg(Color::White)
+        } else {
+            Style::default()
+        };
+
+        if style != run_style && !run.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut run), run_style));
+        }
+        run_style = style;
+        if start < columns.start {
+            run.push_str(&" ".repeat(display_col - columns.start));
+        } else {
+            run.push_str(&text::render_grapheme(grapheme, start));
+        }
+    }
+    if !run.is_empty() {
+        spans.push(Span::styled(run, run_style));
+    }
+    if at_line_end && reached_end && cursor.is_some_and(|col| col >= offset + segment.len()) {
+        spans.push(Span::styled(" ", Style::default().bg(Color::White).fg(Color::Black)));
     }
+    spans
 }
 
 fn centered_rect(width: u16, height: u16, area: Rect) -> Rect {
@@ -0,0 +1,83 @@
+use std::env;
+use std::fs::{self, DirBuilder};
+use std::io;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::history::History;
+use crate::save::write_private;
+
+// Undo histories are cached per file under ~/.cache/oxide/undo, named after a
+// hash of the file's canonical path. Each entry remembers the hash of the file
+// contents it belongs to so a history is only reused if the file is unchanged,
+// and the encoding it was decoded with since edits are recorded against the
+// decoded text.
+#[derive(Serialize, Deserialize)]
+struct UndoFile<H> {
+    path: PathBuf,
+    content_hash: u64,
+    #[serde(default)]
+    encoding: String,
+    history: H,
+}
+
+// FNV-1a, used instead of std's hasher because it must stay stable across builds.
+pub fn hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001b3))
+}
+
+// ~/.cache/oxide, or wherever XDG_CACHE_HOME points.
+pub fn cache_dir() -> Option<PathBuf> {
+    let base = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(base.join("oxide"))
+}
+
+// Makes `dir` and any missing parents, keeping them to the current user since
+// what goes in them are copies of the files being edited.
+pub fn create_private_dir(dir: &Path) -> io::Result<()> {
+    DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    fs::set_permissions(dir, fs::Permissions::from_mode(0o700))
+}
+
+fn entry_for(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    let canonical = fs::canonicalize(path).ok()?;
+    let name = format!("{:016x}.json", hash(canonical.as_os_str().as_encoded_bytes()));
+    Some((canonical, cache_dir()?.join("undo").join(name)))
+}
+
+// `content_hash` must be the hash of the file as it is on disk right now.
+pub fn save(path: &str, content_hash: u64, encoding: &str, history: &History) -> io::Result<()> {
+    let Some((canonical, entry)) = entry_for(Path::new(path)) else { return Ok(()) };
+
+    let undo_file = UndoFile { path: canonical, content_hash, encoding: encoding.to_string(), history };
+    create_private_dir(entry.parent().unwrap())?;
+    write_private(&entry, &serde_json::to_vec(&undo_file)?)
+}
+
+// Returns the cached history for `path` if it was recorded against file
+// contents hashing to `content_hash` read as `encoding`. Entries that no
+// longer match are removed.
+pub fn load(path: &str, content_hash: u64, encoding: &str) -> Option<History> {
+    let (canonical, entry) = entry_for(Path::new(path))?;
+    let data = fs::read(&entry).ok()?;
+
+    match serde_json::from_slice::<UndoFile<History>>(&data) {
+        Ok(undo_file)
+            if undo_file.path == canonical
+                && undo_file.content_hash == content_hash
+                && undo_file.encoding == encoding =>
+        {
+            let mut history = undo_file.history;
+            history.reopen();
+            Some(history)
+        }
+        _ => {
+            let _ = fs::remove_file(entry);
+            None
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use inotify::{Inotify, WatchMask};
+
+// Tells when the open file may have been changed by another program. The
+// directory is watched rather than the file itself so that files replaced by
+// a rename, as editors and git checkouts do, keep being followed.
+pub struct FileWatch {
+    // None when inotify couldn't be set up, in which case the file is simply
+    // checked every time the editor is idle.
+    inotify: Option<Inotify>,
+    name: OsString,
+    buffer: Vec<u8>,
+}
+
+impl FileWatch {
+    pub fn new(path: &Path) -> io::Result<FileWatch> {
+        // A symlink is followed so that changes to the file it points at count.
+        let resolved = fs::canonicalize(path);
+        let path = resolved.as_deref().unwrap_or(path);
+        let name = path.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "not a file"))?;
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+
+        let inotify = Inotify::init()?;
+        inotify.watches().add(
+            dir,
+            WatchMask::CLOSE_WRITE | WatchMask::MODIFY | WatchMask::CREATE | WatchMask::MOVED_TO | WatchMask::DELETE | WatchMask::MOVED_FROM,
+        )?;
+        Ok(FileWatch { inotify: Some(inotify), name: name.to_os_string(), buffer: vec![0; 4096] })
+    }
+
+    pub fn polling() -> FileWatch {
+        FileWatch { inotify: None, name: OsString::new(), buffer: Vec::new() }
+    }
+
+    // Whether anything happened to the file since the last call. Never blocks.
+    pub fn changed(&mut self) -> bool {
+        let Some(inotify) = &mut self.inotify else { return true };
+        let mut changed = false;
+        while let Ok(events) = inotify.read_events(&mut self.buffer) {
+            let mut any = false;
+            for event in events {
+                any = true;
+                changed |= event.name == Some(self.name.as_os_str());
+            }
+            if !any {
+                break;
+            }
+        }
+        changed
+    }
+}